@@ -0,0 +1,13 @@
+// copyright 2022 Remi Bernotavicius
+
+//! Runs a single player game without a browser. Each line of the script is one frame of input,
+//! read from the file given as the first argument or else from stdin, e.g.
+//!
+//!     printf 'right\nright primary\n\nleft\n' | cargo run --example headless
+
+use std::path::PathBuf;
+
+fn main() -> std::io::Result<()> {
+    let script_path = std::env::args_os().nth(1).map(PathBuf::from);
+    frog_quest_battle::run_headless(script_path.as_deref())
+}
@@ -0,0 +1,23 @@
+// copyright 2022 Remi Bernotavicius
+
+//! Builds level files from their text description, e.g.
+//!
+//!     cargo run --example level_compiler -- assets/levels/pond.txt assets/levels/pond.bin
+//!
+//! See `src/level_compiler.rs` for the description format.
+
+use std::io;
+use std::path::PathBuf;
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.as_slice() {
+        [source, output] => {
+            frog_quest_battle::compile_level(&PathBuf::from(source), &PathBuf::from(output))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "usage: level_compiler <level.txt> <output.bin>",
+        )),
+    }
+}
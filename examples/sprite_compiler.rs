@@ -0,0 +1,28 @@
+// copyright 2022 Remi Bernotavicius
+
+//! Builds sprite sheets from BMP images, or dumps them back out to be edited, e.g.
+//!
+//!     cargo run --example sprite_compiler -- dump assets/font.bin assets/font
+//!     cargo run --example sprite_compiler -- compile assets/font.txt assets/font.bin
+//!
+//! See `src/sprite_compiler.rs` for the manifest format.
+
+use std::io;
+use std::path::PathBuf;
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.as_slice() {
+        [mode, manifest, output] if mode == "compile" => frog_quest_battle::compile_sprite_sheet(
+            &PathBuf::from(manifest),
+            &PathBuf::from(output),
+        ),
+        [mode, sheet, prefix] if mode == "dump" => {
+            frog_quest_battle::dump_sprite_sheet(&PathBuf::from(sheet), &PathBuf::from(prefix))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "usage: sprite_compiler compile <manifest> <output.bin> | dump <sheet.bin> <prefix>",
+        )),
+    }
+}
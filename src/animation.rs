@@ -0,0 +1,241 @@
+// copyright 2022 Remi Bernotavicius
+
+use super::graphics::{Assets, Bounds, ColorMap, Orientation, Sheet, Sprite, TileKey};
+use super::renderer::{Pixels, Renderer};
+use bevy::prelude::*;
+use bevy::reflect::impl_reflect_value;
+use euclid::Rect;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The different things an animated sprite can be doing
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum AnimationClip {
+    #[default]
+    Idle,
+    Hop,
+    Flap,
+    Fall,
+    Hurt,
+}
+
+/// Shows `tile` for `duration` game frames
+#[derive(Clone, Copy)]
+pub struct AnimationFrame {
+    pub tile: TileKey,
+    pub duration: u32,
+}
+
+impl AnimationFrame {
+    pub fn new(tile: impl Into<TileKey>, duration: u32) -> Self {
+        assert!(duration > 0);
+        Self {
+            tile: tile.into(),
+            duration,
+        }
+    }
+}
+
+struct ClipFrames {
+    frames: Vec<AnimationFrame>,
+    looping: bool,
+}
+
+impl ClipFrames {
+    fn tile_at(&self, elapsed: u64) -> TileKey {
+        let total: u64 = self.frames.iter().map(|f| f.duration as u64).sum();
+        let mut t = if self.looping {
+            elapsed % total
+        } else {
+            elapsed.min(total - 1)
+        };
+        for frame in &self.frames {
+            if t < frame.duration as u64 {
+                return frame.tile;
+            }
+            t -= frame.duration as u64;
+        }
+        unreachable!()
+    }
+}
+
+/// The frames making up each `AnimationClip` of an animated sprite, all from one `Sheet`
+#[derive(Default)]
+pub struct Animation {
+    sheet: Sheet,
+    clips: HashMap<AnimationClip, ClipFrames>,
+}
+
+impl Animation {
+    pub fn new(sheet: Sheet) -> Self {
+        Self {
+            sheet,
+            clips: HashMap::new(),
+        }
+    }
+
+    /// Adds a clip which starts over once it reaches the end
+    pub fn with_looping_clip(mut self, clip: AnimationClip, frames: Vec<AnimationFrame>) -> Self {
+        self.insert_clip(clip, frames, true);
+        self
+    }
+
+    /// Adds a clip which stays on its last frame once it reaches the end
+    pub fn with_clip(mut self, clip: AnimationClip, frames: Vec<AnimationFrame>) -> Self {
+        self.insert_clip(clip, frames, false);
+        self
+    }
+
+    fn insert_clip(&mut self, clip: AnimationClip, frames: Vec<AnimationFrame>, looping: bool) {
+        assert!(!frames.is_empty(), "{clip:?} has no frames");
+        self.clips.insert(clip, ClipFrames { frames, looping });
+    }
+
+    /// Clips which are missing fall back to `AnimationClip::Idle`
+    fn tile_at(&self, clip: AnimationClip, elapsed: u64) -> Option<TileKey> {
+        let frames = self
+            .clips
+            .get(&clip)
+            .or_else(|| self.clips.get(&AnimationClip::Idle))?;
+        Some(frames.tile_at(elapsed))
+    }
+}
+
+/// A sprite which shows an `AnimationClip` from its `Animation`. The frame shown only depends on
+/// the `FrameCounter` and when the clip was started, so it is advanced as part of stepping the
+/// match and rolled back along with the rest of the game state in a GGRS session.
+#[derive(Component, Clone, Default)]
+pub struct AnimatedSprite {
+    animation: Arc<Animation>,
+    pub colors: ColorMap,
+    pub orientation: Orientation,
+    clip: AnimationClip,
+    clip_start: u64,
+    tile: Option<TileKey>,
+}
+
+impl_reflect_value!(AnimatedSprite);
+
+impl AnimatedSprite {
+    pub fn new(animation: Arc<Animation>, colors: impl Into<ColorMap>) -> Self {
+        let tile = animation.tile_at(AnimationClip::Idle, 0);
+        Self {
+            animation,
+            colors: colors.into(),
+            orientation: Orientation::default(),
+            clip: AnimationClip::Idle,
+            clip_start: 0,
+            tile,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn clip(&self) -> AnimationClip {
+        self.clip
+    }
+
+    /// Switches to the given clip, starting it from the beginning at `frame`. Does nothing if the
+    /// clip is already playing.
+    pub fn play(&mut self, clip: AnimationClip, frame: u64) {
+        if self.clip != clip {
+            self.clip = clip;
+            self.clip_start = frame;
+        }
+    }
+
+    /// Shows the tile of the current clip for the given frame
+    pub fn advance(&mut self, frame: u64) {
+        let elapsed = frame.saturating_sub(self.clip_start);
+        self.tile = self.animation.tile_at(self.clip, elapsed);
+    }
+}
+
+impl Sprite for AnimatedSprite {
+    fn draw(
+        &self,
+        bounds: &Bounds,
+        clip: Rect<i32, Pixels>,
+        assets: &Assets,
+        renderer: &mut impl Renderer,
+    ) {
+        if let Some(tile) = self.tile {
+            assets.sheet(self.animation.sheet).draw_tile(
+                tile,
+                bounds.0.origin,
+                self.colors,
+                self.orientation,
+                clip,
+                renderer,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn animation() -> Animation {
+        Animation::new(Sheet::Font)
+            .with_looping_clip(
+                AnimationClip::Idle,
+                vec![AnimationFrame::new('a', 2), AnimationFrame::new('b', 1)],
+            )
+            .with_clip(
+                AnimationClip::Hurt,
+                vec![AnimationFrame::new('x', 1), AnimationFrame::new('y', 3)],
+            )
+    }
+
+    fn tile_name(tile: Option<TileKey>) -> String {
+        tile.unwrap().to_string()
+    }
+
+    #[test]
+    fn looping_clip_starts_over() {
+        let animation = animation();
+        let tiles: Vec<String> = (0..7)
+            .map(|t| tile_name(animation.tile_at(AnimationClip::Idle, t)))
+            .collect();
+        assert_eq!(tiles, ["a", "a", "b", "a", "a", "b", "a"]);
+    }
+
+    #[test]
+    fn clip_stays_on_last_frame() {
+        let animation = animation();
+        let tiles: Vec<String> = (0..6)
+            .map(|t| tile_name(animation.tile_at(AnimationClip::Hurt, t)))
+            .collect();
+        assert_eq!(tiles, ["x", "y", "y", "y", "y", "y"]);
+    }
+
+    #[test]
+    fn missing_clip_falls_back_to_idle() {
+        let animation = animation();
+        assert_eq!(tile_name(animation.tile_at(AnimationClip::Flap, 2)), "b");
+        assert!(Animation::new(Sheet::Font)
+            .tile_at(AnimationClip::Idle, 0)
+            .is_none());
+    }
+
+    #[test]
+    fn play_restarts_only_new_clips() {
+        let mut sprite = AnimatedSprite::new(
+            Arc::new(animation()),
+            super::super::renderer::PalletColor::Color2,
+        );
+        sprite.play(AnimationClip::Hurt, 10);
+        sprite.advance(11);
+        assert_eq!(tile_name(sprite.tile), "y");
+
+        // Playing the same clip again doesn't restart it
+        sprite.play(AnimationClip::Hurt, 11);
+        sprite.advance(11);
+        assert_eq!(tile_name(sprite.tile), "y");
+
+        sprite.play(AnimationClip::Idle, 11);
+        sprite.advance(13);
+        assert_eq!(tile_name(sprite.tile), "b");
+        assert_eq!(sprite.clip(), AnimationClip::Idle);
+    }
+}
@@ -0,0 +1,306 @@
+// copyright 2022 Remi Bernotavicius
+
+use super::graphics::PALLET;
+use super::renderer::{Framebuffer, Pallet, Renderer, RENDER_RECT};
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+
+#[cfg(target_arch = "wasm32")]
+use {
+    super::window,
+    std::sync::mpsc::{channel, Receiver, Sender},
+    wasm_bindgen::prelude::*,
+    wasm_bindgen::JsCast as _,
+};
+
+/// Sending this event saves the next frame as an image. In the browser the image is downloaded, and
+/// natively it is written to the current directory.
+pub struct Screenshot;
+
+/// Sending this event starts recording frames, and sending it again saves the last few seconds of
+/// them as an animated GIF. Like `Screenshot`, it is downloaded in the browser and written to the
+/// current directory natively.
+pub struct RecordClip;
+
+#[derive(Default)]
+struct ScreenshotCount(u32);
+
+#[cfg(target_arch = "wasm32")]
+enum Hotkey {
+    Screenshot,
+    RecordClip,
+}
+
+#[cfg(target_arch = "wasm32")]
+fn hotkey_from_keyboard_event(e: &web_sys::KeyboardEvent) -> Option<Hotkey> {
+    match e {
+        e if e.code() == "KeyP" => Some(Hotkey::Screenshot),
+        e if e.code() == "KeyG" => Some(Hotkey::RecordClip),
+        _ => None,
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+struct CaptureHotkeys {
+    recv: Receiver<Hotkey>,
+}
+
+#[cfg(target_arch = "wasm32")]
+fn hotkey_source(send: Sender<Hotkey>) {
+    let on_key_down = Closure::wrap(Box::new(move |event: web_sys::KeyboardEvent| {
+        if let Some(h) = hotkey_from_keyboard_event(&event) {
+            if send.send(h).is_ok() {
+                event.prevent_default();
+            }
+        }
+    }) as Box<dyn FnMut(_)>);
+
+    window()
+        .add_event_listener_with_callback("keydown", on_key_down.as_ref().unchecked_ref())
+        .unwrap();
+    on_key_down.forget();
+}
+
+#[cfg(target_arch = "wasm32")]
+impl CaptureHotkeys {
+    fn new() -> Self {
+        let (send, recv) = channel();
+        hotkey_source(send);
+        Self { recv }
+    }
+
+    fn update(
+        self_: NonSend<Self>,
+        mut screenshots: EventWriter<Screenshot>,
+        mut clips: EventWriter<RecordClip>,
+    ) {
+        for hotkey in self_.recv.try_iter() {
+            match hotkey {
+                Hotkey::Screenshot => screenshots.send(Screenshot),
+                Hotkey::RecordClip => clips.send(RecordClip),
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Default for CaptureHotkeys {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn capture<R: Renderer + 'static>(
+    renderer: NonSend<R>,
+    mut count: ResMut<ScreenshotCount>,
+    mut screenshots: EventReader<Screenshot>,
+) {
+    if screenshots.iter().count() > 0 {
+        let mut bytes = vec![];
+        renderer
+            .framebuffer()
+            .to_bmp()
+            .to_writer(&mut bytes)
+            .unwrap();
+        super::save_file(&format!("screenshot-{}.bmp", count.0), &bytes);
+        count.0 += 1;
+    }
+}
+
+/// Only every other frame is recorded, which keeps the clip small and matches the 1/100th of a
+/// second granularity GIF frame delays have.
+const RECORD_INTERVAL: u64 = 2;
+
+/// GIF frame delay in 1/100ths of a second, two 16ms frames.
+const CLIP_FRAME_DELAY: u16 = 3;
+
+/// How many frames are kept, five seconds worth.
+const CLIP_FRAMES: usize = 150;
+
+fn gif_pallet(pallet: &Pallet) -> Vec<u8> {
+    pallet.0.iter().flat_map(|c| [c.r, c.g, c.b]).collect()
+}
+
+/// Keeps the most recent frames drawn while recording so they can be saved as a GIF.
+#[derive(Default)]
+struct Recorder {
+    recording: bool,
+    frames: VecDeque<(Vec<u8>, Pallet)>,
+    frame: u64,
+    clip_count: u32,
+}
+
+impl Recorder {
+    fn start(&mut self) {
+        self.recording = true;
+    }
+
+    /// Encodes the frames recorded so far, and stops recording until started again
+    fn finish(&mut self) -> Result<Vec<u8>, gif::EncodingError> {
+        let bytes = self.encode();
+        self.recording = false;
+        self.frames.clear();
+        self.frame = 0;
+        bytes
+    }
+
+    /// Keeps the frame if recording
+    fn push(&mut self, framebuffer: &Framebuffer) {
+        if !self.recording {
+            return;
+        }
+        if self.frame.is_multiple_of(RECORD_INTERVAL) {
+            if self.frames.len() == CLIP_FRAMES {
+                self.frames.pop_front();
+            }
+            let pixels = framebuffer
+                .pallet_indices()
+                .iter()
+                .map(|c| c.index() as u8)
+                .collect();
+            self.frames.push_back((pixels, framebuffer.pallet()));
+        }
+        self.frame += 1;
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, gif::EncodingError> {
+        let width = RENDER_RECT.size.width as u16;
+        let height = RENDER_RECT.size.height as u16;
+
+        let mut encoder = gif::Encoder::new(vec![], width, height, &gif_pallet(&PALLET))?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+        for (pixels, pallet) in &self.frames {
+            // Frames drawn during a pallet effect bring their own colors
+            encoder.write_frame(&gif::Frame {
+                width,
+                height,
+                delay: CLIP_FRAME_DELAY,
+                buffer: pixels.into(),
+                palette: (*pallet != PALLET).then(|| gif_pallet(pallet)),
+                ..Default::default()
+            })?;
+        }
+        encoder.into_inner()
+    }
+
+    fn update<R: Renderer + 'static>(
+        renderer: NonSend<R>,
+        mut self_: ResMut<Self>,
+        mut clips: EventReader<RecordClip>,
+    ) {
+        let pressed = clips.iter().count() > 0;
+        let was_recording = self_.recording;
+        if pressed && !was_recording {
+            log::info!("recording a clip");
+            self_.start();
+        }
+
+        self_.push(renderer.framebuffer());
+
+        if pressed && was_recording {
+            match self_.finish() {
+                Ok(bytes) => super::save_file(&format!("clip-{}.gif", self_.clip_count), &bytes),
+                Err(e) => log::error!("failed to encode clip: {e}"),
+            }
+            self_.clip_count += 1;
+        }
+    }
+}
+
+pub struct Plugin<R> {
+    renderer: PhantomData<fn() -> R>,
+}
+
+impl<R> Plugin<R> {
+    pub fn new() -> Self {
+        Self {
+            renderer: PhantomData,
+        }
+    }
+}
+
+impl<R: Renderer + 'static> bevy::app::Plugin for Plugin<R> {
+    fn build(&self, app: &mut App) {
+        app.add_event::<Screenshot>()
+            .add_event::<RecordClip>()
+            .init_resource::<ScreenshotCount>()
+            .init_resource::<Recorder>()
+            .add_system(capture::<R>.after("draw_sprites"))
+            .add_system(Recorder::update::<R>.after("draw_sprites"));
+
+        #[cfg(target_arch = "wasm32")]
+        app.init_non_send_resource::<CaptureHotkeys>()
+            .add_system_to_stage(CoreStage::PreUpdate, CaptureHotkeys::update);
+    }
+
+    fn name(&self) -> &str {
+        "capture"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::renderer::{Color, PalletColor};
+    use super::*;
+
+    #[test]
+    fn recorder_keeps_last_frames() {
+        let mut framebuffer = Framebuffer::new();
+        let mut recorder = Recorder::default();
+        recorder.start();
+        for _ in 0..CLIP_FRAMES * RECORD_INTERVAL as usize {
+            recorder.push(&framebuffer);
+        }
+        framebuffer.color_pixel((1, 2).into(), PalletColor::Color3);
+        for _ in 0..RECORD_INTERVAL {
+            recorder.push(&framebuffer);
+        }
+
+        assert_eq!(recorder.frames.len(), CLIP_FRAMES);
+        let (last, _) = recorder.frames.back().unwrap();
+        assert_eq!(last[(2 * RENDER_RECT.size.width + 1) as usize], 2);
+        assert_eq!(last[0], 0);
+    }
+
+    #[test]
+    fn encoded_clip_decodes() {
+        let mut framebuffer = Framebuffer::new();
+        let mut recorder = Recorder::default();
+        recorder.start();
+        framebuffer.set_pallet(PALLET);
+        recorder.push(&framebuffer);
+        recorder.push(&framebuffer);
+        framebuffer.set_pallet(PALLET.map(|_| Color::WHITE));
+        recorder.push(&framebuffer);
+        let bytes = recorder.finish().unwrap();
+
+        let mut decoder = gif::DecodeOptions::new().read_info(&bytes[..]).unwrap();
+        assert_eq!(decoder.width() as i32, RENDER_RECT.size.width);
+        assert_eq!(decoder.height() as i32, RENDER_RECT.size.height);
+        let mut local_pallets = vec![];
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert_eq!(frame.delay, CLIP_FRAME_DELAY);
+            local_pallets.push(frame.palette.clone());
+        }
+        assert_eq!(local_pallets, [None, Some(vec![255; 12])]);
+    }
+
+    #[test]
+    fn recorder_only_records_once_started() {
+        let framebuffer = Framebuffer::new();
+        let mut recorder = Recorder::default();
+        recorder.push(&framebuffer);
+        assert!(recorder.frames.is_empty());
+
+        recorder.start();
+        recorder.push(&framebuffer);
+        assert_eq!(recorder.frames.len(), 1);
+
+        // Saving the clip stops recording
+        recorder.finish().unwrap();
+        assert!(recorder.frames.is_empty());
+        recorder.push(&framebuffer);
+        assert!(recorder.frames.is_empty());
+    }
+}
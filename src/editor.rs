@@ -0,0 +1,841 @@
+// copyright 2022 Remi Bernotavicius
+
+//! Draws sprites in the game itself. The arrow keys move a cursor over a zoomed in view of the
+//! sprite and the primary button paints the pixel under it. The secondary button switches to a
+//! panel to choose the sprite and color, add a new sprite or resize the current one, and to save
+//! the sheet. In the browser the mouse can paint pixels and pick colors and sprites too.
+//!
+//! The editor reads the same `Input`s as the game, so a gamepad drives it in the browser just like
+//! the keyboard: the d-pad or left stick moves, the east button paints and the south button
+//! switches to the panel. Only the keyboard works natively.
+//!
+//! Changes show up in the game straight away, but are only kept if the sheet is saved and copied
+//! over the one in `assets/`.
+
+use super::graphics::{
+    Assets, Bounds, PointIterExt as _, Sheet, SimpleSprite, Sprite, SpriteData, TextBox,
+};
+use super::input::{Input, InputStream};
+use super::renderer::{PalletColor, Pixels, Renderer, RENDER_RECT};
+use super::{despawn_screen, AppState};
+use bevy::prelude::*;
+use euclid::{Point2D, Rect, Size2D, Vector2D};
+
+#[cfg(target_arch = "wasm32")]
+use {
+    std::sync::mpsc::{channel, Receiver, Sender},
+    wasm_bindgen::prelude::*,
+    wasm_bindgen::JsCast as _,
+};
+
+/// The area the zoomed in sprite is drawn in
+const GRID_RECT: Rect<i32, Pixels> = Rect {
+    origin: Point2D::new(10, 24),
+    size: Size2D::new(176, 176),
+};
+
+const PANEL_ORIGIN: Point2D<i32, Pixels> = Point2D::new(200, 24);
+const PANEL_LINE_HEIGHT: i32 = 10;
+
+/// Where the panel's marker goes when the panel doesn't have focus, which is off of the screen
+const HIDDEN_MARKER: Rect<i32, Pixels> = Rect {
+    origin: Point2D::new(-20, 0),
+    size: Size2D::new(10, 10),
+};
+
+const SWATCH_ORIGIN: Point2D<i32, Pixels> = Point2D::new(200, 102);
+const SWATCH_SIZE: i32 = 12;
+const SWATCH_SPACING: i32 = 16;
+
+const PREVIEW_ORIGIN: Point2D<i32, Pixels> = Point2D::new(200, 120);
+/// How many screen pixels across each sprite pixel is drawn in the big preview, when it fits above
+/// the strip
+const PREVIEW_ZOOM: i32 = 3;
+
+const STRIP_ORIGIN: Point2D<i32, Pixels> = Point2D::new(200, 170);
+/// How many sprites are shown on each side of the current one in the strip along the bottom
+const STRIP_NEIGHBORS: usize = 4;
+const STRIP_SPACING: i32 = 14;
+
+#[derive(Component)]
+struct OnEditor;
+
+/// What the arrow keys and primary button are controlling
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Focus {
+    Grid,
+    Panel,
+}
+
+/// The largest width or height a sprite can be resized to
+const MAX_SPRITE_SIZE: i32 = 32;
+
+/// The size of a new sprite when there isn't one selected to copy the size of
+const NEW_SPRITE_SIZE: Size2D<i32, Pixels> = Size2D::new(8, 8);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PanelEntry {
+    Tile,
+    Color,
+    NewSprite,
+    Width,
+    Height,
+    Save,
+    Back,
+}
+
+impl PanelEntry {
+    const ALL: [Self; 7] = [
+        Self::Tile,
+        Self::Color,
+        Self::NewSprite,
+        Self::Width,
+        Self::Height,
+        Self::Save,
+        Self::Back,
+    ];
+}
+
+/// Something the editor needs done outside of itself
+#[derive(Debug, PartialEq, Eq)]
+enum EditorAction {
+    Save(Sheet),
+    Back,
+}
+
+/// Something under the mouse
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+enum Target {
+    Pixel(Point2D<i32, Pixels>),
+    Color(PalletColor),
+    Tile(usize),
+}
+
+fn tile_name(sheet: Sheet, name: &str) -> String {
+    format!("{} '{name}'", sheet.name())
+}
+
+fn color_rect(color: PalletColor) -> Rect<i32, Pixels> {
+    let offset = Vector2D::new(color.index() as i32 * SWATCH_SPACING, 0);
+    Rect::new(
+        SWATCH_ORIGIN + offset,
+        Size2D::new(SWATCH_SIZE, SWATCH_SIZE),
+    )
+}
+
+fn strip_rect(slot: usize) -> Rect<i32, Pixels> {
+    let offset = Vector2D::new(slot as i32 * STRIP_SPACING, 0);
+    Rect::new(STRIP_ORIGIN + offset, Size2D::new(STRIP_SPACING - 2, 16))
+}
+
+fn fill_rect(
+    rect: Rect<i32, Pixels>,
+    color: PalletColor,
+    clip: Rect<i32, Pixels>,
+    renderer: &mut impl Renderer,
+) {
+    for p in rect.intersection(&clip).iter().flat_map(|r| r.point_iter()) {
+        renderer.color_pixel(p, color);
+    }
+}
+
+/// Draws a one pixel line around the outside of `rect`
+fn outline_rect(
+    rect: Rect<i32, Pixels>,
+    color: PalletColor,
+    clip: Rect<i32, Pixels>,
+    renderer: &mut impl Renderer,
+) {
+    let outer = rect.inflate(1, 1);
+    for p in outer
+        .intersection(&clip)
+        .iter()
+        .flat_map(|r| r.point_iter())
+    {
+        if !rect.contains(p) {
+            renderer.color_pixel(p, color);
+        }
+    }
+}
+
+/// Draws every pixel of the sprite as a `zoom` by `zoom` square, including transparent ones
+fn draw_zoomed(
+    data: &SpriteData,
+    origin: Point2D<i32, Pixels>,
+    zoom: i32,
+    clip: Rect<i32, Pixels>,
+    renderer: &mut impl Renderer,
+) {
+    for p in Rect::from_size(data.size).point_iter() {
+        let cell = Rect::new(origin + p.to_vector() * zoom, Size2D::new(zoom, zoom));
+        fill_rect(cell, data.get_pixel(p), clip, renderer);
+    }
+}
+
+/// Edits the sprites in `Assets`
+#[derive(Component)]
+pub struct SpriteEditor {
+    /// Every sprite in every sheet, sorted by sheet and then name
+    tiles: Vec<(Sheet, String)>,
+    tile: usize,
+    cursor: Point2D<i32, Pixels>,
+    color: PalletColor,
+    focus: Focus,
+    entry: usize,
+}
+
+impl SpriteEditor {
+    fn new(assets: &Assets) -> Self {
+        let mut tiles = vec![];
+        for sheet in Sheet::ALL {
+            let mut names: Vec<_> = assets
+                .sheet(sheet)
+                .sprites()
+                .map(|(name, _)| (sheet, name.to_owned()))
+                .collect();
+            names.sort_by(|(_, a), (_, b)| a.cmp(b));
+            tiles.extend(names);
+        }
+        Self {
+            tiles,
+            tile: 0,
+            cursor: Point2D::zero(),
+            color: PalletColor::Color2,
+            focus: Focus::Grid,
+            entry: 0,
+        }
+    }
+
+    fn sprite<'a>(&self, assets: &'a Assets) -> Option<&'a SpriteData> {
+        let (sheet, name) = self.tiles.get(self.tile)?;
+        assets
+            .sheet(*sheet)
+            .sprites()
+            .find_map(|(n, data)| (n == name).then_some(data))
+    }
+
+    fn sprite_mut<'a>(&self, assets: &'a mut Assets) -> Option<&'a mut SpriteData> {
+        let (sheet, name) = self.tiles.get(self.tile)?;
+        assets.sheet_mut(*sheet).sprite_mut(name)
+    }
+
+    /// How many screen pixels across each sprite pixel is drawn in the grid
+    fn zoom(size: Size2D<i32, Pixels>) -> i32 {
+        (GRID_RECT.size.width / size.width)
+            .min(GRID_RECT.size.height / size.height)
+            .max(1)
+    }
+
+    fn grid_rect(&self, assets: &Assets) -> Option<Rect<i32, Pixels>> {
+        let size = self.sprite(assets)?.size;
+        Some(Rect::new(GRID_RECT.origin, size * Self::zoom(size)))
+    }
+
+    fn select_tile(&mut self, tile: usize, assets: &Assets) {
+        self.tile = tile;
+        if let Some(data) = self.sprite(assets) {
+            self.cursor = self
+                .cursor
+                .min(data.size.to_vector().to_point() - Vector2D::new(1, 1));
+        }
+    }
+
+    fn step_tile(&mut self, forward: bool, assets: &Assets) {
+        let len = self.tiles.len();
+        if len > 0 {
+            let tile = if forward {
+                (self.tile + 1) % len
+            } else {
+                (self.tile + len - 1) % len
+            };
+            self.select_tile(tile, assets);
+        }
+    }
+
+    fn step_color(&mut self, forward: bool) {
+        let i = self.color.index();
+        let i = if forward { i + 1 } else { i + 3 };
+        self.color = PalletColor::ALL[i % 4];
+    }
+
+    /// Adds a blank sprite the size of the current one to the current sheet, and selects it. New
+    /// sprites are named `sprite1`, `sprite2` and so on.
+    fn new_sprite(&mut self, assets: &mut Assets) {
+        let (sheet, size) = match (self.tiles.get(self.tile), self.sprite(assets)) {
+            (Some((sheet, _)), Some(data)) => (*sheet, data.size),
+            _ => (Sheet::ALL[0], NEW_SPRITE_SIZE),
+        };
+        let sheet_data = assets.sheet_mut(sheet);
+        let name = (1..)
+            .map(|n| format!("sprite{n}"))
+            .find(|name| !sheet_data.has_sprite(name))
+            .unwrap();
+        let blank = vec![PalletColor::Color1; size.area() as usize];
+        sheet_data.insert_sprite(name.clone(), SpriteData { size, data: blank });
+
+        // Keep the tiles sorted by sheet and then name
+        let at = self
+            .tiles
+            .iter()
+            .position(|(s, n)| *s == sheet && *n > name)
+            .or_else(|| {
+                let last = self.tiles.iter().rposition(|(s, _)| *s == sheet)?;
+                Some(last + 1)
+            })
+            .unwrap_or(self.tiles.len());
+        self.tiles.insert(at, (sheet, name));
+        self.select_tile(at, assets);
+    }
+
+    /// Grows or shrinks the current sprite, keeping its top left
+    fn resize(&mut self, delta: Size2D<i32, Pixels>, assets: &mut Assets) {
+        if let Some(data) = self.sprite_mut(assets) {
+            let size = (data.size + delta).clamp(
+                Size2D::new(1, 1),
+                Size2D::new(MAX_SPRITE_SIZE, MAX_SPRITE_SIZE),
+            );
+            *data = data.resized(size);
+        }
+        self.select_tile(self.tile, assets);
+    }
+
+    fn paint(&mut self, assets: &mut Assets) {
+        let (cursor, color) = (self.cursor, self.color);
+        if let Some(data) = self.sprite_mut(assets) {
+            data.set_pixel(cursor, color);
+        }
+    }
+
+    fn move_cursor(&mut self, delta: Vector2D<i32, Pixels>, assets: &Assets) {
+        if let Some(data) = self.sprite(assets) {
+            let p = self.cursor + delta;
+            self.cursor = Point2D::new(
+                p.x.rem_euclid(data.size.width),
+                p.y.rem_euclid(data.size.height),
+            );
+        }
+    }
+
+    fn input(&mut self, input: Input, assets: &mut Assets) -> Option<EditorAction> {
+        let delta = match input {
+            Input::Up => Vector2D::new(0, -1),
+            Input::Down => Vector2D::new(0, 1),
+            Input::Left => Vector2D::new(-1, 0),
+            Input::Right => Vector2D::new(1, 0),
+            Input::Primary | Input::Secondary => Vector2D::zero(),
+        };
+        match (self.focus, input) {
+            (_, Input::Secondary) => {
+                self.focus = match self.focus {
+                    Focus::Grid => Focus::Panel,
+                    Focus::Panel => Focus::Grid,
+                };
+            }
+            (Focus::Grid, Input::Primary) => self.paint(assets),
+            (Focus::Grid, _) => self.move_cursor(delta, assets),
+            (Focus::Panel, Input::Up | Input::Down) => {
+                let len = PanelEntry::ALL.len() as i32;
+                self.entry = (self.entry as i32 + delta.y).rem_euclid(len) as usize;
+            }
+            (Focus::Panel, Input::Left | Input::Right | Input::Primary) => {
+                let forward = input != Input::Left;
+                let step = if forward { 1 } else { -1 };
+                match PanelEntry::ALL[self.entry] {
+                    PanelEntry::Tile => self.step_tile(forward, assets),
+                    PanelEntry::Color => self.step_color(forward),
+                    PanelEntry::NewSprite if input == Input::Primary => self.new_sprite(assets),
+                    PanelEntry::Width => self.resize(Size2D::new(step, 0), assets),
+                    PanelEntry::Height => self.resize(Size2D::new(0, step), assets),
+                    PanelEntry::Save if input == Input::Primary => {
+                        return self.tiles.get(self.tile).map(|t| EditorAction::Save(t.0));
+                    }
+                    PanelEntry::Back if input == Input::Primary => return Some(EditorAction::Back),
+                    PanelEntry::NewSprite | PanelEntry::Save | PanelEntry::Back => {}
+                }
+            }
+        }
+        None
+    }
+
+    /// The tiles shown in the strip along the bottom, along with which slot they are in
+    fn strip(&self) -> impl Iterator<Item = (usize, usize)> {
+        let first = self.tile.saturating_sub(STRIP_NEIGHBORS);
+        let last = (self.tile + STRIP_NEIGHBORS + 1).min(self.tiles.len());
+        (first..last).enumerate()
+    }
+
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    fn target(&self, p: Point2D<i32, Pixels>, assets: &Assets) -> Option<Target> {
+        if let Some(grid) = self.grid_rect(assets) {
+            if grid.contains(p) {
+                let zoom = Self::zoom(self.sprite(assets)?.size);
+                return Some(Target::Pixel((p - grid.origin.to_vector()) / zoom));
+            }
+        }
+        if let Some(color) = PalletColor::ALL
+            .into_iter()
+            .find(|&c| color_rect(c).contains(p))
+        {
+            return Some(Target::Color(color));
+        }
+        self.strip()
+            .find(|&(slot, _)| strip_rect(slot).contains(p))
+            .map(|(_, tile)| Target::Tile(tile))
+    }
+
+    /// Handles the mouse being clicked, or moved while held down, at `p`
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    fn click(&mut self, p: Point2D<i32, Pixels>, assets: &mut Assets) {
+        match self.target(p, assets) {
+            Some(Target::Pixel(pixel)) => {
+                self.cursor = pixel;
+                self.focus = Focus::Grid;
+                self.paint(assets);
+            }
+            Some(Target::Color(color)) => self.color = color,
+            Some(Target::Tile(tile)) => self.select_tile(tile, assets),
+            None => {}
+        }
+    }
+
+    fn entry_text(&self, entry: PanelEntry, assets: &Assets) -> String {
+        let size = self.sprite(assets).map(|data| data.size);
+        match entry {
+            PanelEntry::Tile => match self.tiles.get(self.tile) {
+                Some((sheet, name)) => format!("tile: {}", tile_name(*sheet, name)),
+                None => "tile: none".into(),
+            },
+            PanelEntry::Color => format!("color: {}", self.color.index() + 1),
+            PanelEntry::NewSprite => "new sprite".into(),
+            PanelEntry::Width => match size {
+                Some(size) => format!("width: {}", size.width),
+                None => "width: none".into(),
+            },
+            PanelEntry::Height => match size {
+                Some(size) => format!("height: {}", size.height),
+                None => "height: none".into(),
+            },
+            PanelEntry::Save => "save".into(),
+            PanelEntry::Back => "back".into(),
+        }
+    }
+
+    fn spawn(mut commands: Commands, assets: Res<Assets>) {
+        TextBox::spawn(&mut commands, "sprite editor", (10, 4), PalletColor::Color3)
+            .insert(OnEditor);
+
+        let editor = Self::new(&assets);
+        let mut pos = PANEL_ORIGIN + Vector2D::new(6, 0);
+        let mut entries = vec![];
+        for entry in PanelEntry::ALL {
+            entries.push(
+                TextBox::spawn(
+                    &mut commands,
+                    editor.entry_text(entry, &assets),
+                    pos,
+                    PalletColor::Color2,
+                )
+                .insert(OnEditor)
+                .id(),
+            );
+            pos.y += PANEL_LINE_HEIGHT;
+        }
+
+        let marker = commands
+            .spawn()
+            .insert(SimpleSprite {
+                tile: '>'.into(),
+                colors: PalletColor::Color4.into(),
+                orientation: Default::default(),
+            })
+            .insert(Bounds(HIDDEN_MARKER))
+            .insert(OnEditor)
+            .id();
+
+        commands
+            .spawn()
+            .insert(editor)
+            .insert(EditorPanel { entries, marker })
+            .insert(Bounds(RENDER_RECT))
+            .insert(OnEditor);
+    }
+
+    fn update(
+        mut query: Query<(&mut Self, &EditorPanel)>,
+        mut textboxes: Query<&mut TextBox>,
+        mut bounds: Query<&mut Bounds>,
+        mut input_stream: NonSendMut<InputStream>,
+        mut assets: ResMut<Assets>,
+        mut app_state: ResMut<State<AppState>>,
+    ) {
+        let (mut self_, panel) = query.single_mut();
+        while let Some(i) = input_stream.get() {
+            match self_.input(i, &mut assets) {
+                Some(EditorAction::Save(sheet)) => assets
+                    .sheet(sheet)
+                    .save_to_file(&format!("{}.bin", sheet.name())),
+                Some(EditorAction::Back) => {
+                    app_state.set(AppState::Menu).unwrap();
+                    return;
+                }
+                None => {}
+            }
+        }
+
+        if self_.is_changed() || assets.is_changed() {
+            panel.update(&self_, &assets, &mut textboxes, &mut bounds);
+        }
+    }
+}
+
+/// The entities showing the state of the `SpriteEditor`
+#[derive(Component)]
+struct EditorPanel {
+    entries: Vec<Entity>,
+    marker: Entity,
+}
+
+impl EditorPanel {
+    fn update(
+        &self,
+        editor: &SpriteEditor,
+        assets: &Assets,
+        textboxes: &mut Query<&mut TextBox>,
+        bounds: &mut Query<&mut Bounds>,
+    ) {
+        for (i, (&entity, entry)) in self.entries.iter().zip(PanelEntry::ALL).enumerate() {
+            let mut text = textboxes.get_mut(entity).unwrap();
+            text.text = editor.entry_text(entry, assets);
+            text.color = if editor.focus == Focus::Panel && i == editor.entry {
+                PalletColor::Color4
+            } else {
+                PalletColor::Color2
+            };
+        }
+
+        let mut marker = bounds.get_mut(self.marker).unwrap();
+        marker.0 = match editor.focus {
+            Focus::Panel => Rect::new(
+                PANEL_ORIGIN + Vector2D::new(0, editor.entry as i32 * PANEL_LINE_HEIGHT),
+                Size2D::new(10, 10),
+            ),
+            Focus::Grid => HIDDEN_MARKER,
+        };
+    }
+}
+
+impl Sprite for SpriteEditor {
+    fn draw(
+        &self,
+        _bounds: &Bounds,
+        clip: Rect<i32, Pixels>,
+        assets: &Assets,
+        renderer: &mut impl Renderer,
+    ) {
+        if let (Some(data), Some(grid)) = (self.sprite(assets), self.grid_rect(assets)) {
+            let zoom = Self::zoom(data.size);
+            draw_zoomed(data, grid.origin, zoom, clip, renderer);
+
+            // Mark the corner of each transparent pixel so the grid is visible
+            for p in Rect::from_size(data.size).point_iter() {
+                let corner = grid.origin + p.to_vector() * zoom;
+                if data.get_pixel(p) == PalletColor::Color1 && clip.contains(corner) {
+                    renderer.color_pixel(corner, PalletColor::Color2);
+                }
+            }
+            outline_rect(grid, PalletColor::Color2, clip, renderer);
+
+            let cursor = Rect::new(
+                grid.origin + self.cursor.to_vector() * zoom,
+                Size2D::new(zoom, zoom),
+            )
+            .inflate(-1, -1);
+            let cursor_color = match data.get_pixel(self.cursor) {
+                PalletColor::Color4 => PalletColor::Color3,
+                _ => PalletColor::Color4,
+            };
+            outline_rect(cursor, cursor_color, clip, renderer);
+
+            draw_zoomed(data, PREVIEW_ORIGIN, 1, clip, renderer);
+            let big_preview = PREVIEW_ORIGIN + Vector2D::new(data.size.width + 4, 0);
+            let room = STRIP_ORIGIN.y - 4 - PREVIEW_ORIGIN.y;
+            let zoom = (room / data.size.height).clamp(1, PREVIEW_ZOOM);
+            draw_zoomed(data, big_preview, zoom, clip, renderer);
+        }
+
+        for color in PalletColor::ALL {
+            let rect = color_rect(color);
+            fill_rect(rect, color, clip, renderer);
+            let outline = if color == self.color {
+                PalletColor::Color4
+            } else {
+                PalletColor::Color2
+            };
+            outline_rect(rect.inflate(1, 1), outline, clip, renderer);
+        }
+
+        for (slot, tile) in self.strip() {
+            let (sheet, name) = &self.tiles[tile];
+            let rect = strip_rect(slot);
+            if let Some((_, data)) = assets.sheet(*sheet).sprites().find(|(n, _)| n == name) {
+                draw_zoomed(
+                    data,
+                    rect.origin,
+                    1,
+                    rect.intersection(&clip).unwrap_or_default(),
+                    renderer,
+                );
+            }
+            if tile == self.tile {
+                outline_rect(rect, PalletColor::Color4, clip, renderer);
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn pointer_source(send: Sender<Point2D<i32, Pixels>>) {
+    let canvas = super::canvas();
+    let on_mouse = Closure::wrap(Box::new(move |event: web_sys::MouseEvent| {
+        // Only when the main button is held down
+        if event.buttons() & 1 == 0 {
+            return;
+        }
+        let canvas = super::canvas();
+        let (width, height) = (canvas.client_width(), canvas.client_height());
+        if width > 0 && height > 0 {
+            let p = Point2D::new(
+                event.offset_x() * RENDER_RECT.size.width / width,
+                event.offset_y() * RENDER_RECT.size.height / height,
+            );
+            if send.send(p).is_ok() {
+                event.prevent_default();
+            }
+        }
+    }) as Box<dyn FnMut(_)>);
+
+    for event in ["mousedown", "mousemove"] {
+        canvas
+            .add_event_listener_with_callback(event, on_mouse.as_ref().unchecked_ref())
+            .unwrap();
+    }
+    on_mouse.forget();
+}
+
+/// Where the mouse was clicked or dragged on the canvas, in render pixels
+#[cfg(target_arch = "wasm32")]
+struct PointerStream {
+    recv: Receiver<Point2D<i32, Pixels>>,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl PointerStream {
+    fn new() -> Self {
+        let (send, recv) = channel();
+        pointer_source(send);
+        Self { recv }
+    }
+
+    /// Runs in every state so clicks outside of the editor are thrown away
+    fn update(
+        self_: NonSend<Self>,
+        mut query: Query<&mut SpriteEditor>,
+        mut assets: ResMut<Assets>,
+    ) {
+        for p in self_.recv.try_iter() {
+            if let Some(mut editor) = query.iter_mut().next() {
+                editor.click(p, &mut assets);
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Default for PointerStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Plugin<R> {
+    renderer: std::marker::PhantomData<fn() -> R>,
+}
+
+impl<R> Plugin<R> {
+    pub fn new() -> Self {
+        Self {
+            renderer: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<R: Renderer + 'static> bevy::app::Plugin for Plugin<R> {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_enter(AppState::SpriteEditor).with_system(SpriteEditor::spawn),
+        )
+        .add_system_set(
+            SystemSet::on_update(AppState::SpriteEditor)
+                .with_system(SpriteEditor::update.before("draw_background"))
+                .with_system(
+                    super::graphics::draw_sprites::<SpriteEditor, R>
+                        .after("draw_background")
+                        .label("draw_sprites"),
+                ),
+        )
+        .add_system_set(
+            SystemSet::on_exit(AppState::SpriteEditor).with_system(despawn_screen::<OnEditor>),
+        );
+
+        #[cfg(target_arch = "wasm32")]
+        app.init_non_send_resource::<PointerStream>()
+            .add_system(PointerStream::update.before("draw_background"));
+    }
+
+    fn name(&self) -> &str {
+        "sprite editor"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures;
+    use super::*;
+
+    /// An editor with the 3x3 `block` sprite of the frog sheet selected
+    fn editor() -> (SpriteEditor, Assets) {
+        let assets = fixtures::assets();
+        let mut editor = SpriteEditor::new(&assets);
+        let block = editor
+            .tiles
+            .iter()
+            .position(|(sheet, name)| *sheet == Sheet::Frog && name == "block")
+            .unwrap();
+        editor.select_tile(block, &assets);
+        (editor, assets)
+    }
+
+    #[test]
+    fn paint_with_cursor() {
+        let (mut editor, mut assets) = editor();
+        for i in [Input::Left, Input::Up, Input::Primary] {
+            assert_eq!(editor.input(i, &mut assets), None);
+        }
+        // The cursor wraps around to the bottom right corner
+        assert_eq!(editor.cursor, Point2D::new(2, 2));
+        let data = editor.sprite(&assets).unwrap();
+        assert_eq!(data.get_pixel(Point2D::new(2, 2)), PalletColor::Color2);
+    }
+
+    #[test]
+    fn panel_changes_tile_and_color() {
+        let (mut editor, mut assets) = editor();
+        let tile = editor.tile;
+        editor.input(Input::Secondary, &mut assets);
+        assert_eq!(editor.focus, Focus::Panel);
+
+        editor.input(Input::Right, &mut assets);
+        assert_eq!(editor.tile, tile + 1);
+        editor.input(Input::Left, &mut assets);
+        editor.input(Input::Left, &mut assets);
+        assert_eq!(editor.tile, tile - 1);
+        editor.input(Input::Right, &mut assets);
+
+        editor.input(Input::Down, &mut assets);
+        editor.input(Input::Left, &mut assets);
+        assert_eq!(editor.color, PalletColor::Color1);
+        assert_eq!(editor.entry_text(PanelEntry::Color, &assets), "color: 1");
+
+        for _ in 0..4 {
+            editor.input(Input::Down, &mut assets);
+        }
+        assert_eq!(
+            editor.input(Input::Primary, &mut assets),
+            Some(EditorAction::Save(Sheet::Frog))
+        );
+        editor.input(Input::Down, &mut assets);
+        assert_eq!(
+            editor.input(Input::Primary, &mut assets),
+            Some(EditorAction::Back)
+        );
+
+        // Going back to the grid paints again
+        editor.input(Input::Secondary, &mut assets);
+        editor.input(Input::Primary, &mut assets);
+        let data = editor.sprite(&assets).unwrap();
+        assert_eq!(data.get_pixel(editor.cursor), PalletColor::Color1);
+    }
+
+    #[test]
+    fn click_targets() {
+        let (mut editor, mut assets) = editor();
+        let zoom = SpriteEditor::zoom(Size2D::new(3, 3));
+
+        editor.click(color_rect(PalletColor::Color3).center(), &mut assets);
+        assert_eq!(editor.color, PalletColor::Color3);
+
+        let pixel = GRID_RECT.origin + Vector2D::new(2 * zoom + 1, zoom + 1);
+        editor.click(pixel, &mut assets);
+        assert_eq!(editor.cursor, Point2D::new(2, 1));
+        let data = editor.sprite(&assets).unwrap();
+        assert_eq!(data.get_pixel(Point2D::new(2, 1)), PalletColor::Color3);
+
+        let tile = editor.tile;
+        let (slot, _) = editor.strip().find(|&(_, t)| t == tile + 1).unwrap();
+        editor.click(strip_rect(slot).origin, &mut assets);
+        assert_eq!(editor.tile, tile + 1);
+
+        // Outside of everything
+        editor.click(Point2D::new(RENDER_RECT.max_x() - 1, 0), &mut assets);
+        assert_eq!(editor.tile, tile + 1);
+    }
+
+    #[test]
+    fn panel_adds_and_resizes_sprites() {
+        let (mut editor, mut assets) = editor();
+        editor.input(Input::Secondary, &mut assets);
+        editor.input(Input::Down, &mut assets);
+        editor.input(Input::Down, &mut assets);
+        assert_eq!(PanelEntry::ALL[editor.entry], PanelEntry::NewSprite);
+
+        // The new sprite is blank and the size of the one it was made from
+        editor.input(Input::Primary, &mut assets);
+        assert_eq!(editor.tiles[editor.tile], (Sheet::Frog, "sprite1".into()));
+        let data = editor.sprite(&assets).unwrap();
+        assert_eq!(data.size, Size2D::new(3, 3));
+        assert!(data.data.iter().all(|&c| c == PalletColor::Color1));
+        assert!(editor
+            .tiles
+            .windows(2)
+            .all(|w| w[0].0 != w[1].0 || w[0].1 < w[1].1));
+
+        editor.input(Input::Primary, &mut assets);
+        assert_eq!(editor.tiles[editor.tile], (Sheet::Frog, "sprite2".into()));
+
+        // Shrinking moves the cursor back inside the sprite, and growing adds transparent pixels
+        editor.cursor = Point2D::new(2, 2);
+        editor
+            .sprite_mut(&mut assets)
+            .unwrap()
+            .set_pixel(Point2D::new(1, 1), PalletColor::Color3);
+        editor.input(Input::Down, &mut assets);
+        editor.input(Input::Left, &mut assets);
+        assert_eq!(editor.entry_text(PanelEntry::Width, &assets), "width: 2");
+        assert_eq!(editor.cursor, Point2D::new(1, 2));
+        editor.input(Input::Down, &mut assets);
+        for _ in 0..2 {
+            editor.input(Input::Right, &mut assets);
+        }
+        let data = editor.sprite(&assets).unwrap();
+        assert_eq!(data.size, Size2D::new(2, 5));
+        assert_eq!(data.get_pixel(Point2D::new(1, 1)), PalletColor::Color3);
+        assert_eq!(data.get_pixel(Point2D::new(1, 4)), PalletColor::Color1);
+
+        // Sprites can't shrink away to nothing
+        for _ in 0..10 {
+            editor.input(Input::Left, &mut assets);
+        }
+        assert_eq!(editor.entry_text(PanelEntry::Height, &assets), "height: 1");
+    }
+}
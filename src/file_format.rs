@@ -0,0 +1,176 @@
+// copyright 2022 Remi Bernotavicius
+
+//! The layout shared by the files the game writes, sprite sheets and levels. A file is:
+//!
+//! - four magic bytes saying what kind of file it is
+//! - the format version, a little-endian u32
+//! - a checksum of the rest of the file, a little-endian u32
+//! - the bincode encoded body
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+
+const HEADER_LEN: usize = 4 + 4 + 4;
+
+/// A kind of file, and the version of it which is written
+pub struct FileFormat {
+    /// What the file holds, as it is called in errors
+    pub name: &'static str,
+    pub magic: &'static [u8; 4],
+    pub version: u32,
+}
+
+#[derive(Debug)]
+pub enum FileErrorKind {
+    /// The file was written by a newer version of the game
+    UnsupportedVersion(u32),
+    /// The file doesn't start with the magic bytes
+    MissingHeader,
+    /// The file ends part way through the header
+    Truncated,
+    ChecksumMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    Decode(bincode::Error),
+    /// The file decoded, but what it holds doesn't make sense
+    Invalid(String),
+}
+
+/// A file which couldn't be read, along with which kind of file it is
+#[derive(Debug)]
+pub struct FileError {
+    pub format: &'static str,
+    pub supported_version: u32,
+    pub kind: FileErrorKind,
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format = self.format;
+        match &self.kind {
+            FileErrorKind::UnsupportedVersion(v) => write!(
+                f,
+                "{format} version {v} is newer than supported version {}",
+                self.supported_version
+            ),
+            FileErrorKind::MissingHeader => write!(f, "{format} header is missing"),
+            FileErrorKind::Truncated => write!(f, "{format} header is truncated"),
+            FileErrorKind::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "{format} checksum {actual:#010x} doesn't match {expected:#010x}"
+            ),
+            FileErrorKind::Decode(e) => write!(f, "failed to decode {format}: {e}"),
+            FileErrorKind::Invalid(e) => write!(f, "invalid {format}: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for FileError {}
+
+/// 32-bit FNV-1a
+pub fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, &b| {
+        (hash ^ b as u32).wrapping_mul(0x01000193)
+    })
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes[..4].try_into().unwrap())
+}
+
+impl FileFormat {
+    pub fn error(&self, kind: FileErrorKind) -> FileError {
+        FileError {
+            format: self.name,
+            supported_version: self.version,
+            kind,
+        }
+    }
+
+    /// Whether the bytes start like a file of this format, though they may still be truncated
+    pub fn has_header(&self, bytes: &[u8]) -> bool {
+        bytes.starts_with(self.magic)
+    }
+
+    /// Checks the header of a file, returning the version it was written in and its body
+    pub fn read<'a>(&self, bytes: &'a [u8]) -> Result<(u32, &'a [u8]), FileError> {
+        if !self.has_header(bytes) {
+            return Err(self.error(FileErrorKind::MissingHeader));
+        }
+        if bytes.len() < HEADER_LEN {
+            return Err(self.error(FileErrorKind::Truncated));
+        }
+        let version = read_u32(&bytes[self.magic.len()..]);
+        if version > self.version {
+            return Err(self.error(FileErrorKind::UnsupportedVersion(version)));
+        }
+        let expected = read_u32(&bytes[self.magic.len() + 4..]);
+        let body = &bytes[HEADER_LEN..];
+        let actual = checksum(body);
+        if actual != expected {
+            return Err(self.error(FileErrorKind::ChecksumMismatch { expected, actual }));
+        }
+        Ok((version, body))
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, body: &[u8]) -> Result<T, FileError> {
+        bincode::deserialize(body).map_err(|e| self.error(FileErrorKind::Decode(e)))
+    }
+
+    /// Encodes `value` as the body of a file in the current version
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub fn write(&self, value: &impl Serialize) -> Vec<u8> {
+        let body = bincode::serialize(value).unwrap();
+        let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+        bytes.extend_from_slice(self.magic);
+        bytes.extend_from_slice(&self.version.to_le_bytes());
+        bytes.extend_from_slice(&checksum(&body).to_le_bytes());
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMAT: FileFormat = FileFormat {
+        name: "test file",
+        magic: b"TEST",
+        version: 2,
+    };
+
+    #[test]
+    fn round_trip() {
+        let bytes = FORMAT.write(&(1u8, "two".to_owned()));
+        let (version, body) = FORMAT.read(&bytes).unwrap();
+        assert_eq!(version, 2);
+        assert_eq!(
+            FORMAT.decode::<(u8, String)>(body).unwrap(),
+            (1, "two".into())
+        );
+    }
+
+    #[test]
+    fn rejects_other_formats() {
+        let other = FileFormat {
+            magic: b"ELSE",
+            ..FORMAT
+        };
+        let error = FORMAT.read(&other.write(&1u8)).err().unwrap();
+        assert!(matches!(error.kind, FileErrorKind::MissingHeader));
+        assert_eq!(error.to_string(), "test file header is missing");
+
+        let newer = FileFormat {
+            version: 3,
+            ..FORMAT
+        };
+        let error = FORMAT.read(&newer.write(&1u8)).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "test file version 3 is newer than supported version 2"
+        );
+    }
+}
@@ -0,0 +1,82 @@
+// copyright 2022 Remi Bernotavicius
+
+//! Sprites and assets shared by the unit tests, so each test draws with the same small sheet
+//! instead of building its own.
+
+use super::graphics::{Assets, PalletPreset, PointIterExt as _, Sheet, SpriteData, SpriteSheet};
+use super::level::{Background, Level, LevelPlatform, TILE_SIZE};
+use super::platform::PlatformKind;
+use super::renderer::{Framebuffer, PalletColor, Pixels, RENDER_RECT};
+use euclid::{Point2D, Rect, Size2D};
+
+/// A sheet of solid sprites:
+///
+/// - `block`, 3x3 in `Color2`
+/// - a 2x3 glyph in `Color2` for each lowercase letter and space
+/// - the `mud`, `water` and `water_2` tiles in `Color3`, `Color2` and `Color4`
+pub fn sheet() -> SpriteSheet {
+    let mut sheet = SpriteSheet::default();
+    let mut insert = |name: &str, size: Size2D<i32, Pixels>, color| {
+        let data = vec![color; size.area() as usize];
+        sheet.insert_sprite(name, SpriteData { size, data });
+    };
+    insert("block", Size2D::new(3, 3), PalletColor::Color2);
+    for c in "abcdefghijklmnopqrstuvwxyz ".chars() {
+        insert(&c.to_string(), Size2D::new(2, 3), PalletColor::Color2);
+    }
+    let tile = Size2D::new(TILE_SIZE, TILE_SIZE);
+    insert("mud", tile, PalletColor::Color3);
+    insert("water", tile, PalletColor::Color2);
+    insert("water_2", tile, PalletColor::Color4);
+    sheet
+}
+
+/// The built in assets, with `sheet` in place of every sprite sheet
+pub fn assets() -> Assets {
+    let mut assets = Assets::default();
+    for s in Sheet::ALL {
+        assets.replace_sheet(s, sheet());
+    }
+    assets
+}
+
+/// A level with two spawn points, a log to stand on and a background of mud above water
+pub fn level() -> Level {
+    Level {
+        name: "test".into(),
+        spawns: vec![Point2D::new(1, 2), Point2D::new(3, 4)],
+        platforms: vec![LevelPlatform {
+            kind: PlatformKind::Log,
+            rect: Rect::new(Point2D::new(0, 100), Size2D::new(50, 8)),
+        }],
+        hazards: vec![],
+        background: Background {
+            tiles: vec![('#', "mud".into()), ('~', "water".into())],
+            rows: vec![".#".into(), "~".into()],
+        },
+        pallet: Some(PalletPreset::GameBoy),
+    }
+}
+
+/// The sprites of a sheet in order of their names, to compare sheets by
+pub fn sprites(sheet: &SpriteSheet) -> Vec<(&str, &SpriteData)> {
+    let mut sprites: Vec<_> = sheet.sprites().collect();
+    sprites.sort_by_key(|&(name, _)| name);
+    sprites
+}
+
+/// The color of the pixel at `x`, `y`
+pub fn pixel(framebuffer: &Framebuffer, x: i32, y: i32) -> PalletColor {
+    framebuffer.pallet_indices()[(y * RENDER_RECT.size.width + x) as usize]
+}
+
+/// Every point on the screen drawn in something other than the background color
+pub fn drawn_pixels(framebuffer: &Framebuffer) -> Vec<Point2D<i32, Pixels>> {
+    RENDER_RECT
+        .point_iter()
+        .filter(|p| {
+            let i = (p.y * RENDER_RECT.size.width + p.x) as usize;
+            framebuffer.pallet_indices()[i] != PalletColor::Color1
+        })
+        .collect()
+}
@@ -1,6 +1,14 @@
 // copyright 2022 Remi Bernotavicius
 
-use super::renderer::{CanvasRenderer, Color, Pixels, RENDER_RECT};
+use super::animation::{AnimatedSprite, Animation, AnimationClip, AnimationFrame};
+use super::level::{Hazard, Level};
+use super::platform::{self, Platform};
+use super::renderer::{PalletColor, Pixels, Renderer, RENDER_RECT};
+use super::rules::{
+    Lives, MatchClock, MatchEnd, MatchResult, MatchRules, Score, Standing, FRAMES_PER_SECOND,
+};
+use super::text::{Align, LINE_HEIGHT};
+use super::tilemap::Tilemap;
 use super::{despawn_screen, graphics, input, AppState};
 use bevy::diagnostic::{Diagnostics, DiagnosticsPlugin, FrameTimeDiagnosticsPlugin};
 use bevy::ecs::system::EntityCommands;
@@ -9,11 +17,14 @@ use bevy::reflect::impl_reflect_value;
 use bevy_ggrs::*;
 use enumset::EnumSet;
 use euclid::{Point2D, Rect, Size2D, Vector2D};
-use graphics::{draw_sprites, Assets, Bounds, PointIterExt as _, Sprite, TextBox, PALLET};
+use graphics::{
+    draw_sprites, Assets, Bounds, ColorMap, Orientation, PalletEffect, ScreenPallet, Sheet,
+    TextBox, WrapHorizontally,
+};
 use input::Input;
 use std::cmp;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher as _};
+use std::marker::PhantomData;
+use std::sync::Arc;
 
 #[derive(Component)]
 struct OnGame;
@@ -22,40 +33,74 @@ struct OnGame;
 pub struct GameStatus(String);
 
 impl GameStatus {
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
     pub fn set_message(&mut self, message: impl Into<String>) {
         self.0 = message.into();
     }
 }
 
-pub struct Plugin {
+pub struct Plugin<R> {
     state: AppState,
+    renderer: PhantomData<fn() -> R>,
 }
 
-impl Plugin {
+impl<R> Plugin<R> {
     pub(super) fn new(state: AppState) -> Self {
-        Self { state }
+        Self {
+            state,
+            renderer: PhantomData,
+        }
     }
 }
 
-impl bevy::app::Plugin for Plugin {
+impl<R: Renderer + 'static> bevy::app::Plugin for Plugin<R> {
     fn build(&self, app: &mut App) {
         app.init_resource::<GameStatus>()
-            .init_resource::<FrameCounter>()
+            .init_resource::<MatchRules>()
+            .insert_rollback_resource(FrameCounter::default())
+            .insert_rollback_resource(MatchClock::default())
+            .insert_rollback_resource(MatchEnd::default())
             .register_rollback_type::<Velocity>()
+            .register_rollback_type::<Player>()
+            .register_rollback_type::<Lives>()
+            .register_rollback_type::<Score>()
+            .register_rollback_type::<AnimatedSprite>()
             .add_plugin(DiagnosticsPlugin)
             .add_plugin(FrameTimeDiagnosticsPlugin)
             .add_system_set(SystemSet::on_enter(self.state).with_system(spawn_sprites))
+            .add_system_set(SystemSet::on_enter(self.state).with_system(start_match))
+            .add_system_set(SystemSet::on_enter(self.state).with_system(spawn_level))
+            .add_system_set(
+                SystemSet::on_update(self.state).with_system(
+                    draw_sprites::<Platform, R>
+                        .after("draw_background")
+                        .label("draw_platforms")
+                        .label("draw_sprites"),
+                ),
+            )
+            .add_system_set(
+                SystemSet::on_update(self.state).with_system(
+                    draw_sprites::<Hazard, R>
+                        .after("draw_background")
+                        .label("draw_platforms")
+                        .label("draw_sprites"),
+                ),
+            )
             .add_system_set(
                 SystemSet::on_update(self.state).with_system(
-                    draw_sprites::<Player>
+                    draw_sprites::<AnimatedSprite, R>
                         .after("draw_background")
+                        .after("draw_platforms")
+                        .after("animate")
                         .label("draw_sprites"),
                 ),
             )
             .add_system_set(SystemSet::on_update(self.state).with_system(FpsCounterTextBox::update))
             .add_system_set(SystemSet::on_update(self.state).with_system(GameStatusTextBox::update))
-            .add_system_set(SystemSet::on_update(self.state).with_system(FrameCounter::update))
-            .add_system_set(SystemSet::on_exit(self.state).with_system(despawn_screen::<OnGame>));
+            .add_system_set(SystemSet::on_update(self.state).with_system(ScoreboardTextBox::update))
+            .add_system_set(SystemSet::on_update(self.state).with_system(end_match.after("step")))
+            .add_system_set(SystemSet::on_exit(self.state).with_system(despawn_screen::<OnGame>))
+            .add_system_set(SystemSet::on_exit(self.state).with_system(leave_level));
     }
 
     fn name(&self) -> &str {
@@ -63,30 +108,119 @@ impl bevy::app::Plugin for Plugin {
     }
 }
 
-#[derive(Component)]
+#[derive(Component, Clone, Default)]
 pub struct Player {
     pub handle: u32,
     last_flap_frame: u64,
+    facing: Facing,
+    /// Set when stomped on by another player. Knocked out players ignore input and can't collide.
+    knocked_out: bool,
+    /// How many frames until a knocked out player comes back, if they have lives left
+    respawn_frames: u32,
+    /// Whether the player is standing on a platform or the ground
+    on_ground: bool,
+    /// Where the player starts the match, and comes back after being knocked out
+    spawn_point: Point2D<i32, Pixels>,
 }
 
-fn arbitrary_color(h: &impl Hash) -> Color {
-    let mut s = DefaultHasher::new();
-    h.hash(&mut s);
-    let index = s.finish();
-    let colors = &PALLET[1..];
+impl_reflect_value!(Player);
 
-    colors[index as usize % colors.len()]
+/// Which way a player is looking. Sprites are drawn facing right.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Facing {
+    Left,
+    #[default]
+    Right,
 }
 
-impl Sprite for Player {
-    fn draw(&self, bounds: &Bounds, _assets: &Assets, renderer: &mut CanvasRenderer) {
-        let color = arbitrary_color(&self.handle);
+/// Every player gets a different color, as long as there are no more players than colors other
+/// than the background.
+fn player_color(handle: u32) -> PalletColor {
+    let colors = &PalletColor::ALL[1..];
+    colors[handle as usize % colors.len()]
+}
 
-        for p in bounds.0.point_iter() {
-            if RENDER_RECT.contains(p) {
-                renderer.color_pixel(p, color);
-            }
-        }
+/// The frog's body is drawn in the player's color, with dark eyes and a belly which stands out from
+/// the body.
+fn player_colors(handle: u32) -> ColorMap {
+    let body = player_color(handle);
+    let belly = if body == PalletColor::Color4 {
+        PalletColor::Color3
+    } else {
+        PalletColor::Color4
+    };
+    ColorMap([PalletColor::Color1, body, PalletColor::Color1, belly])
+}
+
+fn frog_animation() -> Animation {
+    Animation::new(Sheet::Frog)
+        .with_looping_clip(
+            AnimationClip::Idle,
+            vec![AnimationFrame::new("frog_sit", 1)],
+        )
+        .with_looping_clip(
+            AnimationClip::Hop,
+            vec![
+                AnimationFrame::new("frog_jump", 6),
+                AnimationFrame::new("frog_sit", 6),
+            ],
+        )
+        .with_looping_clip(
+            AnimationClip::Flap,
+            vec![
+                AnimationFrame::new("frog_flap_up", 3),
+                AnimationFrame::new("frog_flap_down", 3),
+            ],
+        )
+        .with_looping_clip(
+            AnimationClip::Fall,
+            vec![AnimationFrame::new("frog_jump", 1)],
+        )
+        .with_clip(
+            AnimationClip::Hurt,
+            vec![
+                AnimationFrame::new("frog_flap_up", 4),
+                AnimationFrame::new("frog_flap_down", 4),
+                AnimationFrame::new("frog_flap_up", 4),
+                AnimationFrame::new("frog_jump", 1),
+            ],
+        )
+}
+
+/// How many frames after flapping the player is still drawn flapping
+const FLAP_ANIMATION_FRAMES: u64 = 12;
+
+/// Kicking about once knocked out, sitting when still on the ground, hopping when moving along it,
+/// and otherwise flapping or falling
+fn player_clip(frame: u64, player: &Player, velocity: &Velocity, bounds: &Bounds) -> AnimationClip {
+    let on_ground = player.on_ground || bounds.0.max_y() == RENDER_RECT.max_y();
+    let flapping = player.last_flap_frame > 0
+        && frame.saturating_sub(player.last_flap_frame) < FLAP_ANIMATION_FRAMES;
+
+    if player.knocked_out {
+        AnimationClip::Hurt
+    } else if flapping {
+        AnimationClip::Flap
+    } else if on_ground && velocity.0.x == 0 {
+        AnimationClip::Idle
+    } else if on_ground {
+        AnimationClip::Hop
+    } else {
+        AnimationClip::Fall
+    }
+}
+
+/// Picks what each player is doing and shows the frame of it for the current frame. This runs
+/// right after every `step`, so it is rolled back along with the rest of the match.
+pub fn animate_players(
+    frame_counter: Res<FrameCounter>,
+    mut query: Query<(&Player, &Velocity, &Bounds, &mut AnimatedSprite)>,
+) {
+    for (player, velocity, bounds, mut sprite) in query.iter_mut() {
+        let clip = player_clip(frame_counter.0, player, velocity, bounds);
+        sprite.play(clip, frame_counter.0);
+        sprite.advance(frame_counter.0);
+        sprite.orientation = player.orientation();
     }
 }
 
@@ -94,21 +228,54 @@ impl Player {
     fn new(handle: u32) -> Self {
         Self {
             handle,
-            last_flap_frame: 0,
+            ..Default::default()
         }
     }
+
+    #[cfg(test)]
+    pub fn facing(&self) -> Facing {
+        self.facing
+    }
+
+    /// How to draw a sprite for this player, upside down once knocked out
+    pub fn orientation(&self) -> Orientation {
+        Orientation {
+            flip_x: self.facing == Facing::Left,
+            flip_y: self.knocked_out,
+            ..Default::default()
+        }
+    }
+
+    /// Stops the player where they are until it is time for them to respawn
+    fn knock_out(&mut self, velocity: &mut Velocity) {
+        velocity.0 = Vector2D::zero();
+        self.knocked_out = true;
+        self.respawn_frames = RESPAWN_FRAMES;
+    }
+
+    /// Spawns the player at their spawn point in the level
     pub fn spawn<'a, 'w, 's>(
         commands: &'a mut Commands<'w, 's>,
         handle: u32,
+        rules: &MatchRules,
+        level: &Level,
     ) -> EntityCommands<'w, 's, 'a> {
+        let spawn_point = level.spawn_point(handle);
         let mut entity = commands.spawn();
         entity
-            .insert(Self::new(handle))
-            .insert(Bounds(Rect::new(
-                Point2D::new(10 + handle as i32 * 20, 10),
-                Size2D::new(10, 10),
-            )))
+            .insert(Self {
+                spawn_point,
+                ..Self::new(handle)
+            })
+            .insert(Bounds(Rect::new(spawn_point, Size2D::new(10, 10))))
             .insert(Velocity(Vector2D::zero()))
+            .insert(Lives(rules.lives))
+            .insert(Score(0))
+            .insert(AnimatedSprite::new(
+                Arc::new(frog_animation()),
+                player_colors(handle),
+            ))
+            .insert(WrapHorizontally)
             .insert(OnGame);
         entity
     }
@@ -120,15 +287,16 @@ pub struct Velocity(Vector2D<i32, Pixels>);
 impl_reflect_value!(Velocity);
 
 #[derive(Component)]
-struct FpsCounterTextBox;
+pub(crate) struct FpsCounterTextBox;
 
 impl FpsCounterTextBox {
+    /// The counter is kept against the right side of `rect`, so it doesn't move as it changes
     pub fn spawn<'a, 'w, 's>(
         commands: &'a mut Commands<'w, 's>,
-        pos: impl Into<Point2D<i32, Pixels>>,
-        color: Color,
+        rect: Rect<i32, Pixels>,
+        color: PalletColor,
     ) -> EntityCommands<'w, 's, 'a> {
-        let mut entity = TextBox::spawn(commands, "fps", pos, color);
+        let mut entity = TextBox::spawn_in(commands, "fps", rect, color, Align::Right);
         entity.insert(Self);
         entity
     }
@@ -147,12 +315,13 @@ impl FpsCounterTextBox {
 pub struct GameStatusTextBox;
 
 impl GameStatusTextBox {
+    /// The status is centered in `rect`, wrapping on to more lines if it is too wide
     fn spawn<'a, 'w, 's>(
         commands: &'a mut Commands<'w, 's>,
-        pos: impl Into<Point2D<i32, Pixels>>,
-        color: Color,
+        rect: Rect<i32, Pixels>,
+        color: PalletColor,
     ) -> EntityCommands<'w, 's, 'a> {
-        let mut entity = TextBox::spawn(commands, "", pos, color);
+        let mut entity = TextBox::spawn_in(commands, "", rect, color, Align::Center);
         entity.insert(Self);
         entity
     }
@@ -164,23 +333,160 @@ impl GameStatusTextBox {
     }
 }
 
+/// Shows each player's lives and score, and the time left in timed matches
+#[derive(Component)]
+struct ScoreboardTextBox;
+
+impl ScoreboardTextBox {
+    fn update(
+        rules: Res<MatchRules>,
+        clock: Res<MatchClock>,
+        players: Query<(&Player, &Lives, &Score)>,
+        mut query: Query<&mut TextBox, With<Self>>,
+    ) {
+        let mut players: Vec<_> = players.iter().collect();
+        players.sort_by_key(|(player, _, _)| player.handle);
+        let mut text: Vec<String> = players
+            .into_iter()
+            .map(|(player, lives, score)| {
+                format!("p{} lives {} score {}", player.handle + 1, lives.0, score.0)
+            })
+            .collect();
+        if let Some(frames) = rules.frames_left(&clock) {
+            text.push(format!("time {}", frames.div_ceil(FRAMES_PER_SECOND)));
+        }
+
+        for mut tb in query.iter_mut() {
+            tb.text = text.join("   ");
+        }
+    }
+}
+
+/// Starts the match over from its first frame. This happens before any network session is started
+/// for the match, so it is where the session begins from on every peer.
+fn start_match(
+    mut frame_counter: ResMut<FrameCounter>,
+    mut clock: ResMut<MatchClock>,
+    mut end: ResMut<MatchEnd>,
+) {
+    *frame_counter = FrameCounter::default();
+    *clock = MatchClock::default();
+    *end = MatchEnd::default();
+}
+
+/// How many frames the screen takes to fade out at the end of a match, before the results
+const END_FADE_FRAMES: u32 = 30;
+
+/// How far along `end_match` is with ending the match
+#[derive(Default)]
+enum Ending {
+    #[default]
+    Playing,
+    /// The match was won at `won_at` on the `MatchClock`, and every peer's inputs up to then had
+    /// arrived by `frame`
+    Confirming { won_at: u32, frame: u64 },
+    /// The match is over for certain, and the screen started fading out at `frame`
+    FadingOut { frame: u64 },
+}
+
+/// Fades out, to white when someone won or to black for a draw, and goes to the results screen
+/// once the match is over. Over the network, that is only once every peer's inputs up to the
+/// winning frame have arrived and the match has stepped since, so any rollback to those inputs has
+/// happened and every peer shows the same results.
+#[allow(clippy::too_many_arguments)]
+fn end_match(
+    mut commands: Commands,
+    rules: Res<MatchRules>,
+    frame_counter: Res<FrameCounter>,
+    end: Res<MatchEnd>,
+    session: Option<Res<ggrs::P2PSession>>,
+    players: Query<(&Player, &Lives, &Score)>,
+    mut pallet: ResMut<ScreenPallet>,
+    mut app_state: ResMut<State<AppState>>,
+    mut ending: Local<Ending>,
+) {
+    let frame = frame_counter.frame();
+    match (&*ending, end.0) {
+        (Ending::FadingOut { frame: since }, _) => {
+            if frame < since + END_FADE_FRAMES as u64 {
+                return;
+            }
+        }
+        (_, None) => {
+            *ending = Ending::Playing;
+            return;
+        }
+        (_, Some(won_at)) => {
+            if let Some(session) = session {
+                // The clock reads `won_at` after stepping the frame before it
+                if session.confirmed_frame() < won_at as i32 - 1 {
+                    return;
+                }
+                match *ending {
+                    Ending::Confirming {
+                        won_at: at,
+                        frame: since,
+                    } if at == won_at && frame > since => {}
+                    _ => {
+                        *ending = Ending::Confirming { won_at, frame };
+                        return;
+                    }
+                }
+            }
+            // The players stay as they are once the match is won, so the results are final
+            let result = MatchResult {
+                standings: rules.standings(player_standings(players.iter())),
+                game: *app_state.current(),
+            };
+            pallet.start_effect(match result.winner() {
+                Some(_) => PalletEffect::fade_to_white(END_FADE_FRAMES),
+                None => PalletEffect::fade_to_black(END_FADE_FRAMES),
+            });
+            commands.insert_resource(result);
+            *ending = Ending::FadingOut { frame };
+            return;
+        }
+    }
+    *ending = Ending::Playing;
+    app_state.set(AppState::Results).unwrap();
+}
+
 pub fn spawn_sprites(mut commands: Commands) {
     TextBox::spawn(
         &mut commands,
         "the quick brown fox jumps over the lazy dog",
         (10, 40),
-        PALLET[2],
+        PalletColor::Color3,
     )
     .insert(OnGame);
     TextBox::spawn(
         &mut commands,
         "1234567890!@#$%^&*()-_+={}[]<>,.?/\\`~",
         (10, 56),
-        PALLET[2],
+        PalletColor::Color3,
+    )
+    .insert(OnGame);
+    GameStatusTextBox::spawn(
+        &mut commands,
+        Rect::new(
+            Point2D::new(10, 150),
+            Size2D::new(RENDER_RECT.width() - 20, 40),
+        ),
+        PalletColor::Color3,
     )
     .insert(OnGame);
-    GameStatusTextBox::spawn(&mut commands, (10, 150), PALLET[2]).insert(OnGame);
-    FpsCounterTextBox::spawn(&mut commands, (10, 100), PALLET[2]).insert(OnGame);
+    FpsCounterTextBox::spawn(
+        &mut commands,
+        Rect::new(
+            Point2D::new(10, 100),
+            Size2D::new(RENDER_RECT.width() - 20, 2 * LINE_HEIGHT),
+        ),
+        PalletColor::Color3,
+    )
+    .insert(OnGame);
+    TextBox::spawn(&mut commands, "", (10, 2), PalletColor::Color4)
+        .insert(ScoreboardTextBox)
+        .insert(OnGame);
 }
 
 pub(crate) fn move_player(
@@ -189,12 +495,14 @@ pub(crate) fn move_player(
     player: &mut Player,
     velocity: &mut Velocity,
 ) {
+    if player.knocked_out {
+        return;
+    }
+
     let mut direction = Vector2D::new(0, 0);
-    if input.contains(Input::Primary) {
-        if frame_counter.0 - player.last_flap_frame > 5 {
-            direction.y -= 2;
-            player.last_flap_frame = frame_counter.0;
-        }
+    if input.contains(Input::Primary) && frame_counter.0 - player.last_flap_frame > 5 {
+        direction.y -= 2;
+        player.last_flap_frame = frame_counter.0;
     }
     if input.contains(Input::Left) {
         direction.x -= 1;
@@ -211,27 +519,126 @@ pub(crate) fn move_player(
     } else {
         velocity.0.x = cmp::max(-2, velocity.0.x);
     }
+
+    // keep facing the same way when stopped
+    if velocity.0.x > 0 {
+        player.facing = Facing::Right;
+    } else if velocity.0.x < 0 {
+        player.facing = Facing::Left;
+    }
 }
 
-#[derive(Default)]
+/// How many frames the game has been stepped forward. This is rolled back along with the players,
+/// so flapping and animations line up on every peer.
+#[derive(Component, Clone, Default)]
 pub struct FrameCounter(u64);
 
+impl_reflect_value!(FrameCounter);
+
 impl FrameCounter {
-    fn update(mut self_: ResMut<Self>) {
-        self_.0 += 1;
+    pub fn frame(&self) -> u64 {
+        self.0
     }
 }
 
 // gravity of 1 pixel downward per frame ^2
 const GRAVITY: Vector2D<i32, Pixels> = Vector2D::new(0, 1);
 
-pub fn physics(
+/// The components the game logic steps forward each frame
+pub type PlayerQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static mut Bounds,
+        &'static mut Velocity,
+        &'static mut Player,
+        &'static mut Lives,
+        &'static mut Score,
+    ),
+    With<Player>,
+>;
+
+/// The platforms players collide with. These never move.
+pub type PlatformQuery<'w, 's> =
+    Query<'w, 's, (&'static Bounds, &'static Platform), Without<Player>>;
+
+/// The hazards which knock players out. These never move either.
+pub type HazardQuery<'w, 's> = Query<'w, 's, &'static Bounds, (With<Hazard>, Without<Player>)>;
+
+/// Spawns the background, platforms and hazards of the level chosen in the `MatchRules`, and shows
+/// it in its pallet
+fn spawn_level(
+    mut commands: Commands,
+    rules: Res<MatchRules>,
+    assets: Res<Assets>,
+    mut pallet: ResMut<ScreenPallet>,
+) {
+    let level = assets.level(rules.level());
+    for p in &level.platforms {
+        Platform::spawn(&mut commands, p.kind, p.rect).insert(OnGame);
+    }
+    for h in &level.hazards {
+        Hazard::spawn(&mut commands, h.kind, h.rect).insert(OnGame);
+    }
+    commands
+        .spawn()
+        .insert(Tilemap::new(&level.background))
+        .insert(OnGame);
+    pallet.set_level_preset(level.pallet);
+}
+
+fn leave_level(mut pallet: ResMut<ScreenPallet>) {
+    pallet.set_level_preset(None);
+    pallet.clear_effect();
+}
+
+/// Steps the match forward a frame, after every player has moved. Once the match is won the
+/// players stay as they were, so the results match whichever frame the win is confirmed on.
+pub fn step(
+    frame_counter: &mut FrameCounter,
+    clock: &mut MatchClock,
+    end: &mut MatchEnd,
+    rules: &MatchRules,
+    query: &mut PlayerQuery,
+    platforms: &PlatformQuery,
+    hazards: &HazardQuery,
+) {
+    if end.0.is_none() {
+        let platforms: Vec<_> = platforms.iter().map(|(b, p)| (b.0, p.kind)).collect();
+        physics(frame_counter, query, &platforms);
+        combat(query);
+        touch_hazards(query, hazards);
+        respawn(query);
+    }
+    clock.0 += 1;
+    frame_counter.0 += 1;
+
+    let players = player_standings(query.iter().map(|(_, _, p, l, s)| (p, l, s)));
+    if end.0.is_none() && !players.is_empty() && rules.is_over(clock, &players) {
+        end.0 = Some(clock.0);
+    }
+}
+
+fn player_standings<'a>(
+    players: impl Iterator<Item = (&'a Player, &'a Lives, &'a Score)>,
+) -> Vec<Standing> {
+    players
+        .map(|(player, lives, score)| Standing {
+            handle: player.handle,
+            lives: lives.0,
+            score: score.0,
+        })
+        .collect()
+}
+
+fn physics(
     frame_counter: &FrameCounter,
-    mut query: Query<(&mut Bounds, &mut Velocity, &mut Player)>,
+    query: &mut PlayerQuery,
+    platforms: &[(Rect<i32, Pixels>, platform::PlatformKind)],
 ) {
-    for (mut b, mut v, _) in query.iter_mut() {
-        // apply the velocity
-        b.0.origin += v.0;
+    for (mut b, mut v, mut player, ..) in query.iter_mut() {
+        // apply the velocity, stopping at any platforms in the way
+        platform::move_and_collide(&mut b.0, &mut v.0, platforms);
 
         let above_ceiling = b.0.origin.y <= 0;
         let below_ground = b.0.origin.y + b.0.size.height > RENDER_RECT.size.height;
@@ -249,29 +656,444 @@ pub fn physics(
             v.0.y = 0;
         }
 
-        let on_ground = b.0.origin.y + b.0.size.height == RENDER_RECT.size.height;
+        let on_ground = b.0.origin.y + b.0.size.height == RENDER_RECT.size.height
+            || platform::standing_on_platform(&b.0, platforms);
+        player.on_ground = on_ground;
 
         if on_ground {
             // being on the ground causes a degredation of lateral movement in
             // the direction of movement due to friction
-            if frame_counter.0 % 20 == 0 {
+            if frame_counter.0.is_multiple_of(20) {
                 if v.0.x > 0 {
                     v.0.x -= 1;
                 } else if v.0.x < 0 {
                     v.0.x += 1;
                 }
             }
-        } else if frame_counter.0 % 20 == 0 {
+        } else if frame_counter.0.is_multiple_of(20) {
             // apply gravity to the velocity if not on the ground
             v.0 += GRAVITY;
         }
 
-        // screen wrapping
-        if b.0.origin.x > RENDER_RECT.width() {
-            b.0.origin.x -= RENDER_RECT.width() + b.0.size.width;
+        // screen wrapping, the part of a player past one edge is drawn on the other thanks to
+        // `WrapHorizontally`
+        b.0.origin.x = b.0.origin.x.rem_euclid(RENDER_RECT.width());
+    }
+}
+
+/// How fast a player bounces away after a collision
+const BOUNCE_SPEED: i32 = 2;
+
+/// How long a knocked out player stays down before coming back
+const RESPAWN_FRAMES: u32 = 120;
+
+/// Which of two colliding players stomped on the other
+#[derive(Debug, PartialEq, Eq)]
+enum Stomp {
+    ByA,
+    ByB,
+}
+
+/// How far `b` has to be moved horizontally to overlap `a`, if at all. Players can overlap across
+/// the left and right edges of the screen, since they wrap around.
+fn overlap_offset(a: &Rect<i32, Pixels>, b: &Rect<i32, Pixels>) -> Option<i32> {
+    let width = RENDER_RECT.width();
+    [0, -width, width]
+        .into_iter()
+        .find(|&offset| a.intersects(&b.translate(Vector2D::new(offset, 0))))
+}
+
+/// When two players touch, the one whose feet are higher stomps on the other, knocking them out
+/// and bouncing up off of them. If their feet are level they both bounce apart instead.
+fn collide(
+    (a_bounds, a_velocity, a_player): (&mut Bounds, &mut Velocity, &mut Player),
+    (b_bounds, b_velocity, b_player): (&mut Bounds, &mut Velocity, &mut Player),
+) -> Option<Stomp> {
+    if a_player.knocked_out || b_player.knocked_out {
+        return None;
+    }
+    let offset = overlap_offset(&a_bounds.0, &b_bounds.0)?;
+
+    let a_feet = a_bounds.0.max_y();
+    let b_feet = b_bounds.0.max_y();
+    if a_feet != b_feet {
+        let (winner_velocity, loser_velocity, loser, stomp) = if a_feet < b_feet {
+            (a_velocity, b_velocity, b_player, Stomp::ByA)
+        } else {
+            (b_velocity, a_velocity, a_player, Stomp::ByB)
+        };
+        winner_velocity.0.y = -BOUNCE_SPEED;
+        loser.knock_out(loser_velocity);
+        return Some(stomp);
+    }
+
+    // Push them apart so they no longer overlap, `a` going left if it is further left, or has the
+    // lower handle when they are right on top of each other.
+    let b_rect = b_bounds.0.translate(Vector2D::new(offset, 0));
+    let overlap = a_bounds.0.intersection(&b_rect).unwrap().size.width;
+    let a_left = match a_bounds.0.center().x.cmp(&b_rect.center().x) {
+        cmp::Ordering::Less => true,
+        cmp::Ordering::Greater => false,
+        cmp::Ordering::Equal => a_player.handle < b_player.handle,
+    };
+    let direction = if a_left { -1 } else { 1 };
+    a_bounds.0.origin.x += direction * (overlap / 2);
+    b_bounds.0.origin.x -= direction * (overlap - overlap / 2);
+    a_velocity.0.x = direction * BOUNCE_SPEED;
+    b_velocity.0.x = -direction * BOUNCE_SPEED;
+
+    for bounds in [a_bounds, b_bounds] {
+        bounds.0.origin.x = bounds.0.origin.x.rem_euclid(RENDER_RECT.width());
+    }
+    None
+}
+
+/// Resolves collisions between every pair of players, scoring a point for each stomp and taking a
+/// life from the player stomped on. Players are visited in order of their handles, so the outcome
+/// is the same on every peer.
+fn combat(query: &mut PlayerQuery) {
+    let mut players: Vec<_> = query.iter_mut().collect();
+    players.sort_by_key(|(_, _, player, ..)| player.handle);
+
+    for i in 1..players.len() {
+        let (before, after) = players.split_at_mut(i);
+        let (a_bounds, a_velocity, a_player, a_lives, a_score) = &mut before[i - 1];
+        for (b_bounds, b_velocity, b_player, b_lives, b_score) in after.iter_mut() {
+            let stomp = collide(
+                (&mut **a_bounds, &mut **a_velocity, &mut **a_player),
+                (&mut **b_bounds, &mut **b_velocity, &mut **b_player),
+            );
+            let (score, lives) = match stomp {
+                Some(Stomp::ByA) => (&mut *a_score, &mut *b_lives),
+                Some(Stomp::ByB) => (&mut *b_score, &mut *a_lives),
+                None => continue,
+            };
+            score.0 += 1;
+            lives.0 = lives.0.saturating_sub(1);
+        }
+    }
+}
+
+/// Knocks out any player touching a hazard, taking a life without scoring for anyone
+fn touch_hazards(query: &mut PlayerQuery, hazards: &HazardQuery) {
+    for (bounds, mut velocity, mut player, mut lives, _) in query.iter_mut() {
+        if player.knocked_out {
+            continue;
+        }
+        if hazards
+            .iter()
+            .any(|hazard| overlap_offset(&hazard.0, &bounds.0).is_some())
+        {
+            player.knock_out(&mut velocity);
+            lives.0 = lives.0.saturating_sub(1);
+        }
+    }
+}
+
+/// Brings knocked out players back to where they started once they have been down long enough,
+/// as long as they have lives left.
+fn respawn(query: &mut PlayerQuery) {
+    for (mut bounds, mut velocity, mut player, lives, _) in query.iter_mut() {
+        if !player.knocked_out || lives.0 == 0 {
+            continue;
+        }
+        if player.respawn_frames > 0 {
+            player.respawn_frames -= 1;
+            continue;
+        }
+        player.knocked_out = false;
+        bounds.0.origin = player.spawn_point;
+        velocity.0 = Vector2D::zero();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn player_faces_direction_of_movement() {
+        let frame_counter = FrameCounter(100);
+        let mut player = Player::new(0);
+        let mut velocity = Velocity(Vector2D::zero());
+        let mut step = |input: EnumSet<Input>| {
+            move_player(&frame_counter, input, &mut player, &mut velocity);
+            player.facing()
+        };
+
+        assert_eq!(step(EnumSet::empty()), Facing::Right);
+        assert_eq!(step(Input::Left.into()), Facing::Left);
+        assert_eq!(step(Input::Left.into()), Facing::Left);
+        // still moving left while slowing down
+        assert_eq!(step(Input::Right.into()), Facing::Left);
+        // stopped
+        assert_eq!(step(Input::Right.into()), Facing::Left);
+        assert_eq!(step(Input::Right.into()), Facing::Right);
+        assert!(!player.orientation().flip_x);
+    }
+
+    #[test]
+    fn player_clip_follows_movement() {
+        let mut player = Player::new(0);
+        let ground = RENDER_RECT.size.height - 10;
+        let on_ground = Bounds(Rect::new(Point2D::new(0, ground), Size2D::new(10, 10)));
+        let in_air = Bounds(Rect::new(Point2D::new(0, 50), Size2D::new(10, 10)));
+        let still = Velocity(Vector2D::zero());
+        let moving = Velocity(Vector2D::new(1, 0));
+
+        assert_eq!(
+            player_clip(100, &player, &still, &on_ground),
+            AnimationClip::Idle
+        );
+        assert_eq!(
+            player_clip(100, &player, &moving, &on_ground),
+            AnimationClip::Hop
+        );
+        assert_eq!(
+            player_clip(100, &player, &still, &in_air),
+            AnimationClip::Fall
+        );
+
+        player.last_flap_frame = 95;
+        assert_eq!(
+            player_clip(100, &player, &still, &in_air),
+            AnimationClip::Flap
+        );
+        assert_eq!(
+            player_clip(95 + FLAP_ANIMATION_FRAMES, &player, &still, &in_air),
+            AnimationClip::Fall
+        );
+
+        // Knocked out players are hurt however they are moving
+        player.knocked_out = true;
+        assert_eq!(
+            player_clip(100, &player, &still, &in_air),
+            AnimationClip::Hurt
+        );
+        assert_eq!(
+            player_clip(100, &player, &moving, &on_ground),
+            AnimationClip::Hurt
+        );
+    }
+
+    fn player(handle: u32, x: i32, y: i32) -> (Bounds, Velocity, Player) {
+        (
+            Bounds(Rect::new(Point2D::new(x, y), Size2D::new(10, 10))),
+            Velocity(Vector2D::new(1, 1)),
+            Player::new(handle),
+        )
+    }
+
+    fn collide_players(
+        a: &mut (Bounds, Velocity, Player),
+        b: &mut (Bounds, Velocity, Player),
+    ) -> Option<Stomp> {
+        collide(
+            (&mut a.0, &mut a.1, &mut a.2),
+            (&mut b.0, &mut b.1, &mut b.2),
+        )
+    }
+
+    #[test]
+    fn higher_player_stomps_lower_player() {
+        let mut a = player(0, 50, 50);
+        let mut b = player(1, 55, 45);
+        assert_eq!(collide_players(&mut a, &mut b), Some(Stomp::ByB));
+
+        assert!(a.2.knocked_out);
+        assert_eq!(a.1 .0, Vector2D::zero());
+        assert!(!b.2.knocked_out);
+        assert_eq!(b.1 .0, Vector2D::new(1, -BOUNCE_SPEED));
+        assert!(a.2.orientation().flip_y);
+
+        // Knocked out players ignore input and don't collide again
+        move_player(&FrameCounter(100), Input::Left.into(), &mut a.2, &mut a.1);
+        assert_eq!(a.1 .0, Vector2D::zero());
+        let mut c = player(2, 50, 40);
+        assert_eq!(collide_players(&mut a, &mut c), None);
+        assert!(!c.2.knocked_out);
+        assert_eq!(c.1 .0, Vector2D::new(1, 1));
+    }
+
+    #[test]
+    fn level_players_bounce_apart() {
+        let mut a = player(0, 54, 50);
+        let mut b = player(1, 50, 50);
+        assert_eq!(collide_players(&mut a, &mut b), None);
+
+        assert!(!a.2.knocked_out && !b.2.knocked_out);
+        assert_eq!(a.0 .0.origin.x, 57);
+        assert_eq!(b.0 .0.origin.x, 47);
+        assert_eq!(a.1 .0, Vector2D::new(BOUNCE_SPEED, 1));
+        assert_eq!(b.1 .0, Vector2D::new(-BOUNCE_SPEED, 1));
+
+        // Players which aren't touching are left alone
+        collide_players(&mut a, &mut b);
+        assert_eq!(a.0 .0.origin.x, 57);
+        assert_eq!(b.0 .0.origin.x, 47);
+    }
+
+    #[test]
+    fn players_collide_across_the_screen_edge() {
+        let right = RENDER_RECT.width() - 5;
+        let mut a = player(0, right, 50);
+        let mut b = player(1, 2, 50);
+        collide_players(&mut a, &mut b);
+
+        assert_eq!(a.0 .0.origin.x, right - 1);
+        assert_eq!(b.0 .0.origin.x, 4);
+
+        // Players right on top of each other are told apart by their handles
+        let mut a = player(1, 0, 50);
+        let mut b = player(0, 0, 50);
+        collide_players(&mut a, &mut b);
+        assert_eq!(a.0 .0.origin.x, 5);
+        assert_eq!(b.0 .0.origin.x, RENDER_RECT.width() - 5);
+    }
+
+    #[test]
+    fn stomps_score_and_players_respawn() {
+        use bevy::ecs::system::SystemState;
+
+        let mut world = World::new();
+        let rules = MatchRules::default();
+        let assets = Assets::default();
+        let level = assets.level(0);
+        let mut spawn = |handle, x, y| {
+            let mut commands_state = SystemState::<Commands>::new(&mut world);
+            let mut commands = commands_state.get_mut(&mut world);
+            let entity = Player::spawn(&mut commands, handle, &rules, level).id();
+            commands_state.apply(&mut world);
+            world.get_mut::<Bounds>(entity).unwrap().0.origin = Point2D::new(x, y);
+            entity
+        };
+        let a = spawn(0, 50, 50);
+        let b = spawn(1, 55, 45);
+
+        let mut frame_counter = FrameCounter(1);
+        let mut clock = MatchClock::default();
+        let mut end = MatchEnd::default();
+        let mut query_state =
+            SystemState::<(PlayerQuery, PlatformQuery, HazardQuery)>::new(&mut world);
+        let mut step_world = |world: &mut World| {
+            let (mut query, platforms, hazards) = query_state.get_mut(world);
+            step(
+                &mut frame_counter,
+                &mut clock,
+                &mut end,
+                &rules,
+                &mut query,
+                &platforms,
+                &hazards,
+            );
+        };
+
+        step_world(&mut world);
+        assert!(world.get::<Player>(a).unwrap().knocked_out);
+        assert_eq!(world.get::<Lives>(a), Some(&Lives(rules.lives - 1)));
+        assert_eq!(world.get::<Score>(b), Some(&Score(1)));
+        assert_eq!(world.get::<Score>(a), Some(&Score(0)));
+
+        for _ in 0..=RESPAWN_FRAMES {
+            step_world(&mut world);
         }
-        if b.0.origin.x < -b.0.size.width {
-            b.0.origin.x += RENDER_RECT.width() + b.0.size.width;
+        assert!(!world.get::<Player>(a).unwrap().knocked_out);
+        assert_eq!(
+            world.get::<Bounds>(a).unwrap().0.origin,
+            level.spawn_point(0)
+        );
+
+        // Without any lives left, a knocked out player stays down
+        world.get_mut::<Lives>(a).unwrap().0 = 0;
+        world.get_mut::<Player>(a).unwrap().knocked_out = true;
+        for _ in 0..=RESPAWN_FRAMES {
+            step_world(&mut world);
         }
+        assert!(world.get::<Player>(a).unwrap().knocked_out);
+    }
+
+    #[test]
+    fn hazards_knock_players_out() {
+        use super::super::level::HazardKind;
+        use bevy::ecs::system::SystemState;
+
+        let mut world = World::new();
+        let rules = MatchRules::default();
+        let assets = Assets::default();
+        let mut commands_state = SystemState::<Commands>::new(&mut world);
+        let mut commands = commands_state.get_mut(&mut world);
+        let player = Player::spawn(&mut commands, 0, &rules, assets.level(0)).id();
+        Hazard::spawn(
+            &mut commands,
+            HazardKind::Water,
+            Rect::new(Point2D::new(0, 100), Size2D::new(20, 8)),
+        );
+        commands_state.apply(&mut world);
+        world.get_mut::<Bounds>(player).unwrap().0.origin =
+            Point2D::new(RENDER_RECT.width() - 5, 95);
+
+        let mut query_state =
+            SystemState::<(PlayerQuery, PlatformQuery, HazardQuery)>::new(&mut world);
+        let (mut query, platforms, hazards) = query_state.get_mut(&mut world);
+        step(
+            &mut FrameCounter(1),
+            &mut MatchClock::default(),
+            &mut MatchEnd::default(),
+            &rules,
+            &mut query,
+            &platforms,
+            &hazards,
+        );
+
+        // The player wraps around the edge of the screen in to the water
+        assert!(world.get::<Player>(player).unwrap().knocked_out);
+        assert_eq!(world.get::<Lives>(player), Some(&Lives(rules.lives - 1)));
+        assert_eq!(world.get::<Score>(player), Some(&Score(0)));
+    }
+
+    #[test]
+    fn the_match_ends_while_stepping() {
+        use super::super::level::HazardKind;
+        use bevy::ecs::system::SystemState;
+
+        let mut world = World::new();
+        let mut rules = MatchRules::default();
+        rules.lives = 1;
+        let assets = Assets::default();
+        let mut commands_state = SystemState::<Commands>::new(&mut world);
+        let mut commands = commands_state.get_mut(&mut world);
+        let player = Player::spawn(&mut commands, 0, &rules, assets.level(0)).id();
+        Hazard::spawn(
+            &mut commands,
+            HazardKind::Water,
+            Rect::new(Point2D::new(0, 100), Size2D::new(20, 8)),
+        );
+        commands_state.apply(&mut world);
+        world.get_mut::<Bounds>(player).unwrap().0.origin = Point2D::new(0, 95);
+
+        let mut frame_counter = FrameCounter(1);
+        let mut clock = MatchClock(10);
+        let mut end = MatchEnd::default();
+        let mut query_state =
+            SystemState::<(PlayerQuery, PlatformQuery, HazardQuery)>::new(&mut world);
+        for _ in 0..=RESPAWN_FRAMES {
+            let (mut query, platforms, hazards) = query_state.get_mut(&mut world);
+            step(
+                &mut frame_counter,
+                &mut clock,
+                &mut end,
+                &rules,
+                &mut query,
+                &platforms,
+                &hazards,
+            );
+        }
+
+        // The last frog fell in the water on the first frame, and stays there after the match is
+        // over while the clock keeps going
+        assert_eq!(end.0, Some(11));
+        assert_eq!(clock.0, 11 + RESPAWN_FRAMES);
+        assert!(world.get::<Player>(player).unwrap().knocked_out);
+        assert_eq!(world.get::<Lives>(player), Some(&Lives(0)));
     }
 }
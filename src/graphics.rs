@@ -1,15 +1,20 @@
 // copyright 2022 Remi Bernotavicius
 
-use super::renderer::{CanvasRenderer, Color, Pixels, RENDER_RECT};
+use super::game::FrameCounter;
+use super::level::{self, Level};
+use super::renderer::{Color, Pallet, PalletColor, Pixels, Renderer, RENDER_RECT};
+use super::text::{self, Align};
+use super::tilemap::Tilemap;
 use bevy::ecs::system::EntityCommands;
 use bevy::prelude::*;
 use bevy::reflect::impl_reflect_value;
 use bevy_ggrs::*;
-use euclid::{Point2D, Rect, Size2D};
+use euclid::{Point2D, Rect, Size2D, Vector2D};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use wasm_bindgen::JsValue;
+use std::marker::PhantomData;
+use std::sync::Mutex;
 
 pub struct PointIterator<T, U> {
     i: Point2D<T, U>,
@@ -43,7 +48,7 @@ where
         if !self.rect.contains(self.i) {
             None
         } else {
-            let ret = self.i.clone();
+            let ret = self.i;
             self.advance();
             Some(ret)
         }
@@ -60,8 +65,8 @@ where
 {
     fn point_iter(&self) -> PointIterator<T, U> {
         PointIterator {
-            i: self.origin.clone(),
-            rect: self.clone(),
+            i: self.origin,
+            rect: *self,
         }
     }
 }
@@ -73,7 +78,7 @@ where
     fn point_iter(&self) -> PointIterator<T, U> {
         PointIterator {
             i: Point2D::origin(),
-            rect: self.clone().into(),
+            rect: (*self).into(),
         }
     }
 }
@@ -83,24 +88,54 @@ pub struct Bounds(pub Rect<i32, Pixels>);
 
 impl_reflect_value!(Bounds);
 
-pub struct Plugin;
+/// Limits drawing of a sprite to the given area of the screen. Without this sprites are only
+/// limited to `RENDER_RECT`.
+#[derive(Component, Clone)]
+pub struct Clip(pub Rect<i32, Pixels>);
 
-impl bevy::app::Plugin for Plugin {
+/// Sprites with this are drawn as if the left and right edges of the screen were joined, so any
+/// part past one edge shows up on the other. This matches the screen wrapping in `game::physics`.
+#[derive(Component, Clone)]
+pub struct WrapHorizontally;
+
+pub struct Plugin<R> {
+    renderer: PhantomData<fn() -> R>,
+}
+
+impl<R> Plugin<R> {
+    pub fn new() -> Self {
+        Self {
+            renderer: PhantomData,
+        }
+    }
+}
+
+impl<R: Renderer + 'static> bevy::app::Plugin for Plugin<R> {
     fn build(&self, app: &mut App) {
         app.init_resource::<Assets>()
+            .init_resource::<ScreenPallet>()
             .register_rollback_type::<Bounds>()
-            .add_system(draw_background.label("draw_background"))
+            .add_system(Tilemap::update.before("draw_background"))
+            .add_system(draw_background::<R>.after("step").label("draw_background"))
+            .add_system(TextBox::fit_bounds.label("fit_text"))
             .add_system(
-                draw_sprites::<TextBox>
+                draw_sprites::<TextBox, R>
                     .after("draw_background")
+                    .after("fit_text")
                     .label("draw_sprites"),
             )
             .add_system(
-                draw_sprites::<SimpleSprite>
+                draw_sprites::<SimpleSprite, R>
                     .after("draw_background")
                     .label("draw_sprites"),
             )
-            .add_system(flip_buffer.after("draw_sprites"));
+            .add_system(ScreenPallet::update::<R>.label("pallet"))
+            .add_system(
+                flip_buffer::<R>
+                    .after("draw_sprites")
+                    .after("pallet")
+                    .label("flip_buffer"),
+            );
     }
 
     fn name(&self) -> &str {
@@ -108,15 +143,7 @@ impl bevy::app::Plugin for Plugin {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub enum PalletColor {
-    Color1,
-    Color2,
-    Color3,
-    Color4,
-}
-
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct SpriteData {
     pub size: Size2D<i32, Pixels>,
     pub data: Vec<PalletColor>,
@@ -127,9 +154,122 @@ impl SpriteData {
         self.size.into()
     }
 
-    pub fn get_pixel(&self, pos: Point2D<i32, Pixels>) -> PalletColor {
+    fn index(&self, pos: Point2D<i32, Pixels>) -> usize {
         assert!(self.rect().contains(pos));
-        self.data[usize::try_from(pos.y * self.size.width + pos.x).unwrap()]
+        usize::try_from(pos.y * self.size.width + pos.x).unwrap()
+    }
+
+    pub fn get_pixel(&self, pos: Point2D<i32, Pixels>) -> PalletColor {
+        self.data[self.index(pos)]
+    }
+
+    pub fn set_pixel(&mut self, pos: Point2D<i32, Pixels>, color: PalletColor) {
+        let i = self.index(pos);
+        self.data[i] = color;
+    }
+
+    /// A copy of the sprite at a different size, cut off or filled out with transparent pixels
+    /// along the right and bottom
+    pub fn resized(&self, size: Size2D<i32, Pixels>) -> Self {
+        let mut resized = Self {
+            size,
+            data: vec![PalletColor::Color1; size.area() as usize],
+        };
+        for p in self
+            .rect()
+            .intersection(&size.into())
+            .iter()
+            .flat_map(|r| r.point_iter())
+        {
+            resized.set_pixel(p, self.get_pixel(p));
+        }
+        resized
+    }
+}
+
+/// Which `PalletColor` each `PalletColor` in a sprite is drawn as. `Color1` in a sprite is always
+/// transparent, so what it maps to doesn't matter.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ColorMap(pub [PalletColor; 4]);
+
+impl ColorMap {
+    /// Draws sprites in their own colors
+    pub const IDENTITY: Self = Self(PalletColor::ALL);
+
+    /// Draws the `Color2` parts of a sprite in the given color, like the ink of a font glyph.
+    pub fn tint(color: PalletColor) -> Self {
+        let mut map = PalletColor::ALL;
+        map[PalletColor::Color2.index()] = color;
+        Self(map)
+    }
+
+    pub fn get(&self, c: PalletColor) -> PalletColor {
+        self.0[c.index()]
+    }
+}
+
+impl Default for ColorMap {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl From<PalletColor> for ColorMap {
+    fn from(color: PalletColor) -> Self {
+        Self::tint(color)
+    }
+}
+
+/// Clockwise quarter turns
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Rotation {
+    #[default]
+    None,
+    #[allow(dead_code)]
+    Quarter,
+    #[allow(dead_code)]
+    Half,
+    #[allow(dead_code)]
+    ThreeQuarters,
+}
+
+/// How a sprite is turned when drawn. Flipping happens before rotating.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Orientation {
+    /// Mirror left to right
+    pub flip_x: bool,
+    /// Mirror top to bottom
+    pub flip_y: bool,
+    pub rotation: Rotation,
+}
+
+impl Orientation {
+    /// The size of a sprite of the given size once drawn
+    fn drawn_size(&self, size: Size2D<i32, Pixels>) -> Size2D<i32, Pixels> {
+        match self.rotation {
+            Rotation::None | Rotation::Half => size,
+            Rotation::Quarter | Rotation::ThreeQuarters => Size2D::new(size.height, size.width),
+        }
+    }
+
+    /// Given a position in the drawn sprite, returns the position in the sprite's data to draw
+    /// there.
+    fn source_pixel(
+        &self,
+        drawn: Point2D<i32, Pixels>,
+        size: Size2D<i32, Pixels>,
+    ) -> Point2D<i32, Pixels> {
+        let (w, h) = (size.width, size.height);
+        let (x, y) = (drawn.x, drawn.y);
+        let (x, y) = match self.rotation {
+            Rotation::None => (x, y),
+            Rotation::Quarter => (y, h - 1 - x),
+            Rotation::Half => (w - 1 - x, h - 1 - y),
+            Rotation::ThreeQuarters => (w - 1 - y, x),
+        };
+        let x = if self.flip_x { w - 1 - x } else { x };
+        let y = if self.flip_y { h - 1 - y } else { y };
+        Point2D::new(x, y)
     }
 }
 
@@ -160,27 +300,34 @@ impl fmt::Display for TileKey {
     }
 }
 
+/// The sprite drawn in place of any which are missing from a sheet
+const MISSING_TILE: &str = "missing";
+
+/// The character with the other case, if the character has a case and it's a single character
+fn other_case(c: char) -> Option<char> {
+    let mut other: Vec<char> = if c.is_lowercase() {
+        c.to_uppercase().collect()
+    } else {
+        c.to_lowercase().collect()
+    };
+    match (other.pop(), other.is_empty()) {
+        (Some(o), true) if o != c => Some(o),
+        _ => None,
+    }
+}
+
 #[derive(Default, Serialize, Deserialize)]
 pub struct SpriteSheet {
     sprites: HashMap<String, SpriteData>,
+    /// Tiles which were drawn but aren't in the sheet, so each is only warned about once
+    #[serde(skip)]
+    missing: Mutex<HashSet<String>>,
 }
 
 impl SpriteSheet {
-    #[allow(dead_code)]
-    pub fn save_to_file(&self, window: &web_sys::Window) -> Result<(), JsValue> {
-        let bytes = bincode::serialize(self).unwrap();
-        let u8_array = js_sys::Uint8Array::new_with_length(bytes.len() as u32);
-        u8_array.copy_from(&bytes);
-        let array = js_sys::Array::new_with_length(1);
-        array.set(0, u8_array.buffer().into());
-        let blob = web_sys::Blob::new_with_buffer_source_sequence_and_options(
-            &array,
-            web_sys::BlobPropertyBag::new().type_("application/octet-stream"),
-        )?;
-        let url = web_sys::Url::create_object_url_with_blob(&blob)?;
-        window.location().set_href(&url)?;
-
-        Ok(())
+    /// Saves the sheet as `name`. In the browser this offers it as a download.
+    pub fn save_to_file(&self, name: &str) {
+        super::save_file(name, &self.to_bytes())
     }
 
     fn get_sprite_data(&self, tile: TileKey) -> Option<&SpriteData> {
@@ -190,45 +337,219 @@ impl SpriteSheet {
         }
     }
 
-    #[allow(dead_code)]
-    pub fn insert_sprite(&mut self, tile: TileKey, data: SpriteData) {
-        match tile {
-            TileKey::Char(c) => self.sprites.insert(c.to_string(), data),
-            TileKey::Str(s) => self.sprites.insert(s.into(), data),
-        };
+    /// Like `get_sprite_data`, but characters the sheet doesn't have can be found in the other case
+    fn find_sprite_data(&self, tile: TileKey) -> Option<&SpriteData> {
+        self.get_sprite_data(tile).or_else(|| match tile {
+            TileKey::Char(c) => self.get_sprite_data(other_case(c)?.into()),
+            TileKey::Str(_) => None,
+        })
+    }
+
+    /// Whether drawing the character draws its own sprite rather than the missing sprite
+    #[cfg(test)]
+    pub fn supports(&self, c: char) -> bool {
+        self.find_sprite_data(c.into()).is_some()
+    }
+
+    /// Every character which has a sprite of its own, in no particular order. Characters which are
+    /// drawn with the sprite for their other case aren't included.
+    #[cfg(test)]
+    pub fn supported_chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.sprites.keys().filter_map(|name| {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(c),
+                _ => None,
+            }
+        })
+    }
+
+    /// Adds a sprite drawn with the `TileKey` of the same name, a single character or a string.
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub fn insert_sprite(&mut self, name: impl Into<String>, data: SpriteData) {
+        self.sprites.insert(name.into(), data);
+    }
+
+    pub fn sprite_mut(&mut self, name: &str) -> Option<&mut SpriteData> {
+        self.sprites.get_mut(name)
+    }
+
+    /// Every sprite along with its name, in no particular order
+    pub fn sprites(&self) -> impl Iterator<Item = (&str, &SpriteData)> {
+        self.sprites
+            .iter()
+            .map(|(name, data)| (name.as_str(), data))
+    }
+
+    /// Whether the sheet has a sprite with exactly this name
+    pub fn has_sprite(&self, name: &str) -> bool {
+        self.sprites.contains_key(name)
     }
 
-    fn draw_tile(
+    /// The sprite drawn for the tile, which is the missing sprite if the sheet doesn't have it
+    fn resolve(&self, tile: TileKey) -> Option<&SpriteData> {
+        self.find_sprite_data(tile)
+            .or_else(|| self.missing_sprite(&tile.to_string()))
+    }
+
+    /// The missing sprite, drawn in place of the named one
+    fn missing_sprite(&self, name: &str) -> Option<&SpriteData> {
+        if self.missing.lock().unwrap().insert(name.to_owned()) {
+            log::warn!("tile key {name:?} not found, drawing {MISSING_TILE:?} instead");
+        }
+        self.sprites.get(MISSING_TILE)
+    }
+
+    /// The size `draw_tile` draws the tile at without any rotation
+    pub fn tile_size(&self, tile: TileKey) -> Size2D<i32, Pixels> {
+        self.resolve(tile).map(|data| data.size).unwrap_or_default()
+    }
+
+    pub fn draw_tile(
         &self,
         tile: TileKey,
         p: Point2D<i32, Pixels>,
-        color: Color,
-        renderer: &mut CanvasRenderer,
+        colors: ColorMap,
+        orientation: Orientation,
+        clip: Rect<i32, Pixels>,
+        renderer: &mut impl Renderer,
+    ) -> Size2D<i32, Pixels> {
+        match self.resolve(tile) {
+            Some(data) => Self::draw_data(data, p, colors, orientation, clip, renderer),
+            None => Size2D::zero(),
+        }
+    }
+
+    /// Like `draw_tile`, for sprites whose names are only known while the game is running
+    pub fn draw_named(
+        &self,
+        name: &str,
+        p: Point2D<i32, Pixels>,
+        colors: ColorMap,
+        orientation: Orientation,
+        clip: Rect<i32, Pixels>,
+        renderer: &mut impl Renderer,
+    ) -> Size2D<i32, Pixels> {
+        let data = self.sprites.get(name).or_else(|| self.missing_sprite(name));
+        match data {
+            Some(data) => Self::draw_data(data, p, colors, orientation, clip, renderer),
+            None => Size2D::zero(),
+        }
+    }
+
+    fn draw_data(
+        data: &SpriteData,
+        p: Point2D<i32, Pixels>,
+        colors: ColorMap,
+        orientation: Orientation,
+        clip: Rect<i32, Pixels>,
+        renderer: &mut impl Renderer,
     ) -> Size2D<i32, Pixels> {
-        let data = self
-            .get_sprite_data(tile)
-            .ok_or_else(|| format!("tile key {tile} not found"))
-            .unwrap();
-
-        for tile_pixel in data.size.point_iter() {
-            let pixel = data.get_pixel(tile_pixel);
-            if pixel == PalletColor::Color2 {
-                renderer.color_pixel(p + tile_pixel.to_vector(), color);
+        let size = orientation.drawn_size(data.size);
+        let visible = Rect::new(p, size).intersection(&clip);
+        for screen_pixel in visible.iter().flat_map(|v| v.point_iter()) {
+            let drawn = screen_pixel - p.to_vector();
+            let pixel = data.get_pixel(orientation.source_pixel(drawn, data.size));
+            if pixel != PalletColor::Color1 {
+                renderer.color_pixel(screen_pixel, colors.get(pixel));
             }
         }
 
-        data.size
+        size
+    }
+}
+
+/// One of the `SpriteSheet`s in `Assets`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Sheet {
+    #[default]
+    Font,
+    Frog,
+    /// The tiles level backgrounds are made of
+    Tiles,
+}
+
+impl Sheet {
+    pub const ALL: [Self; 3] = [Self::Font, Self::Frog, Self::Tiles];
+
+    /// The name of the sheet's file in `assets/`, without the extension
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Font => "font",
+            Self::Frog => "frog",
+            Self::Tiles => "tiles",
+        }
     }
 }
 
 pub struct Assets {
     font: SpriteSheet,
+    frog: SpriteSheet,
+    tiles: SpriteSheet,
+    /// Colors to use instead of the built in ones for some `PalletPreset`s
+    pallets: HashMap<PalletPreset, Pallet>,
+    /// The arenas to choose from, in the same order as `level::BUILTIN`
+    levels: Vec<Level>,
+}
+
+impl Assets {
+    pub fn sheet(&self, sheet: Sheet) -> &SpriteSheet {
+        match sheet {
+            Sheet::Font => &self.font,
+            Sheet::Frog => &self.frog,
+            Sheet::Tiles => &self.tiles,
+        }
+    }
+
+    pub fn sheet_mut(&mut self, sheet: Sheet) -> &mut SpriteSheet {
+        match sheet {
+            Sheet::Font => &mut self.font,
+            Sheet::Frog => &mut self.frog,
+            Sheet::Tiles => &mut self.tiles,
+        }
+    }
+
+    pub fn replace_sheet(&mut self, sheet: Sheet, sprites: SpriteSheet) {
+        match sheet {
+            Sheet::Font => self.font = sprites,
+            Sheet::Frog => self.frog = sprites,
+            Sheet::Tiles => self.tiles = sprites,
+        }
+    }
+
+    pub fn pallet(&self, preset: PalletPreset) -> Pallet {
+        self.pallets
+            .get(&preset)
+            .copied()
+            .unwrap_or_else(|| preset.pallet())
+    }
+
+    /// Replaces the colors of the given presets. Any preset not included goes back to its built in
+    /// colors.
+    pub fn replace_pallets(&mut self, pallets: HashMap<PalletPreset, Pallet>) {
+        self.pallets = pallets;
+    }
+
+    pub fn level(&self, index: usize) -> &Level {
+        &self.levels[index]
+    }
+
+    pub fn replace_level(&mut self, index: usize, level: Level) {
+        self.levels[index] = level;
+    }
 }
 
 impl Default for Assets {
     fn default() -> Self {
         let s = Self {
-            font: bincode::deserialize(include_bytes!("../assets/font.bin")).unwrap(),
+            font: SpriteSheet::from_bytes(include_bytes!("../assets/font.bin"))
+                .expect("assets/font.bin is a valid sprite sheet"),
+            frog: SpriteSheet::from_bytes(include_bytes!("../assets/frog.bin"))
+                .expect("assets/frog.bin is a valid sprite sheet"),
+            tiles: SpriteSheet::from_bytes(include_bytes!("../assets/tiles.bin"))
+                .expect("assets/tiles.bin is a valid sprite sheet"),
+            pallets: HashMap::new(),
+            levels: level::builtin_levels(),
         };
         let mut keys = s.font.sprites.keys().cloned().collect::<Vec<String>>();
         keys.sort();
@@ -240,60 +561,144 @@ impl Default for Assets {
 #[derive(Component)]
 pub struct SimpleSprite {
     pub tile: TileKey,
-    pub color: Color,
+    pub colors: ColorMap,
+    pub orientation: Orientation,
 }
 
 impl Sprite for SimpleSprite {
-    fn draw(&self, bounds: &Bounds, assets: &Assets, renderer: &mut CanvasRenderer) {
-        assets
-            .font
-            .draw_tile(self.tile, bounds.0.origin.clone(), self.color, renderer);
+    fn draw(
+        &self,
+        bounds: &Bounds,
+        clip: Rect<i32, Pixels>,
+        assets: &Assets,
+        renderer: &mut impl Renderer,
+    ) {
+        assets.font.draw_tile(
+            self.tile,
+            bounds.0.origin,
+            self.colors,
+            self.orientation,
+            clip,
+            renderer,
+        );
     }
 }
 
+/// Text drawn in the font. Lines are split at each '\n', and each line is placed between the left
+/// and right sides of the `Bounds` according to `align`.
 #[derive(Component)]
 pub struct TextBox {
     pub text: String,
-    pub color: Color,
+    pub color: PalletColor,
+    pub align: Align,
+    /// Whether lines are wrapped to fit in the width of the `Bounds`. Otherwise the `Bounds` are
+    /// resized to fit the text whenever it changes.
+    pub wrap: bool,
 }
 
 impl TextBox {
-    pub fn new(text: impl Into<String>, color: Color) -> Self {
+    pub fn new(text: impl Into<String>, color: PalletColor) -> Self {
         Self {
             text: text.into(),
             color,
+            align: Align::Left,
+            wrap: false,
         }
     }
 
+    /// Spawns text with its top left corner at `pos`, with `Bounds` the size of the text.
     pub fn spawn<'a, 'w, 's>(
         commands: &'a mut Commands<'w, 's>,
         text: impl Into<String>,
         pos: impl Into<Point2D<i32, Pixels>>,
-        color: Color,
+        color: PalletColor,
     ) -> EntityCommands<'w, 's, 'a> {
         let mut entity = commands.spawn();
         entity
             .insert(TextBox::new(text, color))
-            .insert(Bounds(Rect::new(pos.into(), Size2D::new(100, 10))));
+            .insert(Bounds(Rect::new(pos.into(), Size2D::zero())));
         entity
     }
+
+    /// Spawns text wrapped to fit in the width of `rect`, and aligned within it. Lines which don't
+    /// fit in the height of `rect` are cut off.
+    pub fn spawn_in<'a, 'w, 's>(
+        commands: &'a mut Commands<'w, 's>,
+        text: impl Into<String>,
+        rect: Rect<i32, Pixels>,
+        color: PalletColor,
+        align: Align,
+    ) -> EntityCommands<'w, 's, 'a> {
+        let mut entity = commands.spawn();
+        entity
+            .insert(TextBox {
+                align,
+                wrap: true,
+                ..TextBox::new(text, color)
+            })
+            .insert(Bounds(rect))
+            .insert(Clip(rect));
+        entity
+    }
+
+    fn lines(&self, bounds: &Bounds, font: &SpriteSheet) -> Vec<String> {
+        let width = self.wrap.then_some(bounds.0.size.width);
+        text::lines(&self.text, font, width)
+    }
+
+    fn fit_bounds(assets: Res<Assets>, mut query: Query<(&Self, &mut Bounds), Changed<Self>>) {
+        for (text_box, mut bounds) in query.iter_mut() {
+            if !text_box.wrap {
+                bounds.0.size = text::measure(&text_box.text, &assets.font, None);
+            }
+        }
+    }
 }
 
 impl Sprite for TextBox {
-    fn draw(&self, bounds: &Bounds, assets: &Assets, renderer: &mut CanvasRenderer) {
-        let mut p = bounds.0.origin.clone();
-        for c in self.text.chars() {
-            let size = assets.font.draw_tile(c.into(), p, self.color, renderer);
-            p.x += size.width;
+    fn draw(
+        &self,
+        bounds: &Bounds,
+        clip: Rect<i32, Pixels>,
+        assets: &Assets,
+        renderer: &mut impl Renderer,
+    ) {
+        let colors = ColorMap::tint(self.color);
+        for (i, line) in self.lines(bounds, &assets.font).iter().enumerate() {
+            let line_width = text::text_width(line, &assets.font);
+            let mut p = bounds.0.origin
+                + Vector2D::new(
+                    self.align.offset(line_width, bounds.0.size.width),
+                    i as i32 * text::LINE_HEIGHT,
+                );
+            for c in line.chars() {
+                let size = assets.font.draw_tile(
+                    c.into(),
+                    p,
+                    colors,
+                    Orientation::default(),
+                    clip,
+                    renderer,
+                );
+                p.x += size.width;
+            }
         }
     }
 }
 
 pub trait Sprite {
-    fn draw(&self, bounds: &Bounds, assets: &Assets, renderer: &mut CanvasRenderer);
+    /// Draws the sprite, only touching pixels inside of `clip`, which is always within
+    /// `RENDER_RECT`.
+    fn draw(
+        &self,
+        bounds: &Bounds,
+        clip: Rect<i32, Pixels>,
+        assets: &Assets,
+        renderer: &mut impl Renderer,
+    );
 }
 
-pub const PALLET: [Color; 4] = [
+pub const PALLET: Pallet = Pallet([
     Color { r: 6, g: 35, b: 39 },
     Color {
         r: 28,
@@ -310,27 +715,680 @@ pub const PALLET: [Color; 4] = [
         g: 232,
         b: 150,
     },
-];
+]);
+
+/// The pallets to choose from. Each one goes from the background color to the brightest.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PalletPreset {
+    Classic,
+    HighContrast,
+    /// Avoids telling colors apart by red versus green
+    Deuteranopia,
+    GameBoy,
+}
+
+impl PalletPreset {
+    pub const ALL: [Self; 4] = [
+        Self::Classic,
+        Self::HighContrast,
+        Self::Deuteranopia,
+        Self::GameBoy,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Classic => "classic",
+            Self::HighContrast => "high contrast",
+            Self::Deuteranopia => "deuteranopia",
+            Self::GameBoy => "game boy",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name() == name)
+    }
 
-const BG_COLOR: Color = PALLET[0];
+    pub fn pallet(self) -> Pallet {
+        let c = |r, g, b| Color { r, g, b };
+        match self {
+            Self::Classic => PALLET,
+            Self::HighContrast => {
+                Pallet([c(0, 0, 0), c(0, 170, 255), c(255, 255, 0), c(255, 255, 255)])
+            }
+            Self::Deuteranopia => Pallet([
+                c(16, 16, 32),
+                c(0, 114, 178),
+                c(230, 159, 0),
+                c(240, 240, 240),
+            ]),
+            Self::GameBoy => Pallet([
+                c(15, 56, 15),
+                c(48, 98, 48),
+                c(139, 172, 15),
+                c(202, 220, 159),
+            ]),
+        }
+    }
+
+    /// The preset after this one, going back to the first after the last
+    pub fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|&p| p == self).unwrap();
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+}
+
+const BG_COLOR: PalletColor = PalletColor::Color1;
+
+/// How many frames each half of a `PalletEffect::Flash` lasts
+const FLASH_PERIOD: u32 = 4;
+
+/// A temporary change to the colors of everything on screen
+#[derive(Clone, Copy, Debug)]
+pub enum PalletEffect {
+    /// Gradually turns every color into `to`, which then stays until the effect is cleared
+    FadeOut { to: Color, frames: u32 },
+    /// Gradually turns every color from `from` back to normal
+    #[allow(dead_code)]
+    FadeIn { from: Color, frames: u32 },
+    /// Switches every color between `color` and normal
+    #[allow(dead_code)]
+    Flash { color: Color, frames: u32 },
+}
+
+impl PalletEffect {
+    pub fn fade_to_black(frames: u32) -> Self {
+        Self::FadeOut {
+            to: Color::BLACK,
+            frames,
+        }
+    }
+
+    pub fn fade_to_white(frames: u32) -> Self {
+        Self::FadeOut {
+            to: Color::WHITE,
+            frames,
+        }
+    }
+
+    fn apply(&self, pallet: Pallet, elapsed: u32) -> Pallet {
+        match *self {
+            Self::FadeOut { to, frames } => {
+                pallet.map(|c| c.blend(to, elapsed.min(frames), frames))
+            }
+            Self::FadeIn { from, frames } => {
+                pallet.map(|c| from.blend(c, elapsed.min(frames), frames))
+            }
+            Self::Flash { color, .. } => {
+                if (elapsed / FLASH_PERIOD).is_multiple_of(2) {
+                    pallet.map(|_| color)
+                } else {
+                    pallet
+                }
+            }
+        }
+    }
+
+    fn finished(&self, elapsed: u32) -> bool {
+        match *self {
+            Self::FadeOut { .. } => false,
+            Self::FadeIn { frames, .. } | Self::Flash { frames, .. } => elapsed >= frames,
+        }
+    }
+}
+
+/// The colors everything on screen is displayed with. Changing these affects what has already been
+/// drawn.
+pub struct ScreenPallet {
+    preset: PalletPreset,
+    /// The pallet of the level being played, shown instead of the classic pallet
+    level_preset: Option<PalletPreset>,
+    effect: Option<PalletEffect>,
+    elapsed: u32,
+}
+
+/// The name the chosen `PalletPreset` is saved under
+const PALLET_SETTING: &str = "pallet";
 
-fn draw_background(mut renderer: NonSendMut<CanvasRenderer>) {
-    for p in RENDER_RECT.point_iter() {
-        renderer.color_pixel(p, BG_COLOR);
+impl Default for ScreenPallet {
+    fn default() -> Self {
+        let preset = super::load_setting(PALLET_SETTING)
+            .and_then(|name| PalletPreset::from_name(&name))
+            .unwrap_or(PalletPreset::Classic);
+        Self {
+            preset,
+            level_preset: None,
+            effect: None,
+            elapsed: 0,
+        }
     }
 }
 
-pub fn draw_sprites<S: Sprite + Component>(
+impl ScreenPallet {
+    pub fn preset(&self) -> PalletPreset {
+        self.preset
+    }
+
+    /// Changes the colors used when no effect is happening, and remembers the choice for next time.
+    pub fn set_preset(&mut self, preset: PalletPreset) {
+        self.preset = preset;
+        super::save_setting(PALLET_SETTING, preset.name());
+    }
+
+    /// Shows a level in its own pallet while it is played, unless a pallet other than the classic
+    /// one was chosen. `None` goes back to the chosen pallet.
+    pub fn set_level_preset(&mut self, preset: Option<PalletPreset>) {
+        self.level_preset = preset;
+    }
+
+    /// The preset the screen is currently shown in
+    fn shown_preset(&self) -> PalletPreset {
+        match (self.preset, self.level_preset) {
+            (PalletPreset::Classic, Some(level)) => level,
+            (preset, _) => preset,
+        }
+    }
+
+    /// Replaces any effect already happening with the given one
+    pub fn start_effect(&mut self, effect: PalletEffect) {
+        self.effect = Some(effect);
+        self.elapsed = 0;
+    }
+
+    pub fn clear_effect(&mut self) {
+        self.effect = None;
+    }
+
+    fn current(&self, assets: &Assets) -> Pallet {
+        let pallet = assets.pallet(self.shown_preset());
+        match &self.effect {
+            Some(effect) => effect.apply(pallet, self.elapsed),
+            None => pallet,
+        }
+    }
+
+    fn update<R: Renderer + 'static>(
+        mut self_: ResMut<Self>,
+        assets: Res<Assets>,
+        mut renderer: NonSendMut<R>,
+    ) {
+        renderer.set_pallet(self_.current(&assets));
+
+        if let Some(effect) = self_.effect {
+            self_.elapsed += 1;
+            if effect.finished(self_.elapsed) {
+                self_.clear_effect();
+            }
+        }
+    }
+}
+
+/// Draws the `Tilemap` if there is one, otherwise clears the screen to the background color
+fn draw_background<R: Renderer + 'static>(
+    mut renderer: NonSendMut<R>,
     assets: Res<Assets>,
-    mut renderer: NonSendMut<CanvasRenderer>,
-    query: Query<(&Bounds, &S)>,
+    frame_counter: Option<Res<FrameCounter>>,
+    tilemaps: Query<&Tilemap>,
+) {
+    match tilemaps.iter().next() {
+        Some(tilemap) => {
+            let frame = frame_counter.map_or(0, |f| f.frame());
+            tilemap.draw(frame, &assets, &mut *renderer);
+        }
+        None => {
+            for p in RENDER_RECT.point_iter() {
+                renderer.color_pixel(p, BG_COLOR);
+            }
+        }
+    }
+}
+
+pub fn draw_sprites<S: Sprite + Component, R: Renderer + 'static>(
+    assets: Res<Assets>,
+    mut renderer: NonSendMut<R>,
+    query: Query<(&Bounds, &S, Option<&Clip>, Option<&WrapHorizontally>)>,
+) {
+    for (b, s, clip, wrap) in query.iter() {
+        let clip = match clip {
+            Some(clip) => clip.0.intersection(&RENDER_RECT),
+            None => Some(RENDER_RECT),
+        };
+        if let Some(clip) = clip {
+            s.draw(b, clip, &assets, &mut *renderer);
+            if wrap.is_some() {
+                draw_wrapped(s, b, clip, &assets, &mut *renderer);
+            }
+        }
+    }
+}
+
+/// Draws the parts of the sprite past the left or right edge of the screen on the other side.
+fn draw_wrapped(
+    sprite: &impl Sprite,
+    bounds: &Bounds,
+    clip: Rect<i32, Pixels>,
+    assets: &Assets,
+    renderer: &mut impl Renderer,
 ) {
-    for (b, s) in query.iter() {
-        s.draw(b, &*assets, &mut *renderer);
+    for offset in [-RENDER_RECT.width(), RENDER_RECT.width()] {
+        let wrapped = Bounds(bounds.0.translate(Vector2D::new(offset, 0)));
+        if wrapped.0.intersects(&clip) {
+            sprite.draw(&wrapped, clip, assets, renderer);
+        }
     }
 }
 
-fn flip_buffer(mut renderer: NonSendMut<CanvasRenderer>) {
+fn flip_buffer<R: Renderer + 'static>(mut renderer: NonSendMut<R>) {
     renderer.present();
     renderer.render();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures::{self, drawn_pixels};
+    use super::super::renderer::Framebuffer;
+    use super::*;
+
+    #[test]
+    fn fade_out_stays_faded() {
+        let mut pallet = ScreenPallet {
+            preset: PalletPreset::Classic,
+            level_preset: None,
+            effect: None,
+            elapsed: 0,
+        };
+        pallet.start_effect(PalletEffect::fade_to_black(2));
+        assert_eq!(pallet.current(&Assets::default()), PALLET);
+
+        pallet.elapsed = 1;
+        assert_eq!(
+            pallet.current(&Assets::default()),
+            PALLET.map(|c| c.blend(Color::BLACK, 1, 2))
+        );
+
+        pallet.elapsed = 100;
+        assert_eq!(
+            pallet.current(&Assets::default()),
+            PALLET.map(|_| Color::BLACK)
+        );
+    }
+
+    #[test]
+    fn fade_in_and_flash_finish() {
+        let effect = PalletEffect::FadeIn {
+            from: Color::WHITE,
+            frames: 3,
+        };
+        assert_eq!(effect.apply(PALLET, 0), PALLET.map(|_| Color::WHITE));
+        assert_eq!(effect.apply(PALLET, 3), PALLET);
+        assert!(!effect.finished(2));
+        assert!(effect.finished(3));
+
+        let effect = PalletEffect::Flash {
+            color: Color::WHITE,
+            frames: FLASH_PERIOD * 4,
+        };
+        assert_eq!(effect.apply(PALLET, 0), PALLET.map(|_| Color::WHITE));
+        assert_eq!(effect.apply(PALLET, FLASH_PERIOD), PALLET);
+        assert!(effect.finished(FLASH_PERIOD * 4));
+    }
+
+    #[test]
+    fn fades_without_frames_switch_straight_away() {
+        let effect = PalletEffect::fade_to_black(0);
+        assert_eq!(effect.apply(PALLET, 0), PALLET.map(|_| Color::BLACK));
+
+        let effect = PalletEffect::FadeIn {
+            from: Color::WHITE,
+            frames: 0,
+        };
+        assert_eq!(effect.apply(PALLET, 0), PALLET);
+        assert!(effect.finished(0));
+    }
+
+    #[test]
+    fn pallet_presets_have_distinct_colors() {
+        for preset in PalletPreset::ALL {
+            let colors = preset.pallet().0;
+            for (i, a) in colors.iter().enumerate() {
+                assert!(
+                    !colors[(i + 1)..].contains(a),
+                    "{preset:?} repeats color {a:?}"
+                );
+            }
+            assert_eq!(PalletPreset::from_name(preset.name()), Some(preset));
+        }
+        assert_eq!(PalletPreset::GameBoy.next(), PalletPreset::Classic);
+    }
+
+    #[test]
+    fn level_pallet_only_replaces_classic() {
+        let mut pallet = ScreenPallet {
+            preset: PalletPreset::Classic,
+            level_preset: Some(PalletPreset::GameBoy),
+            effect: None,
+            elapsed: 0,
+        };
+        assert_eq!(pallet.shown_preset(), PalletPreset::GameBoy);
+
+        pallet.preset = PalletPreset::HighContrast;
+        assert_eq!(pallet.shown_preset(), PalletPreset::HighContrast);
+
+        pallet.preset = PalletPreset::Classic;
+        pallet.set_level_preset(None);
+        assert_eq!(pallet.shown_preset(), PalletPreset::Classic);
+    }
+
+    #[test]
+    fn draw_tile_clips_to_screen_edges_and_corners() {
+        let w = RENDER_RECT.size.width;
+        let h = RENDER_RECT.size.height;
+        let cases = [
+            ("left", (-1, 10), 6),
+            ("right", (w - 2, 10), 6),
+            ("top", (10, -2), 3),
+            ("bottom", (10, h - 1), 3),
+            ("top left", (-1, -1), 4),
+            ("top right", (w - 1, -1), 2),
+            ("bottom left", (-2, h - 1), 1),
+            ("bottom right", (w - 1, h - 1), 1),
+            ("off left", (-3, 10), 0),
+            ("off right", (w, 10), 0),
+            ("off top", (10, -3), 0),
+            ("off bottom", (10, h), 0),
+            ("off corner", (w, h), 0),
+        ];
+
+        let sheet = fixtures::sheet();
+        for (name, p, expected) in cases {
+            let mut framebuffer = Framebuffer::new();
+            let p = Point2D::from(p);
+            let size = sheet.draw_tile(
+                "block".into(),
+                p,
+                PalletColor::Color3.into(),
+                Orientation::default(),
+                RENDER_RECT,
+                &mut framebuffer,
+            );
+            assert_eq!(size, Size2D::new(3, 3));
+
+            let drawn = drawn_pixels(&framebuffer);
+            assert_eq!(drawn.len(), expected, "{name}");
+            assert!(
+                drawn.iter().all(|d| Rect::new(p, size).contains(*d)),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn draw_tile_clips_to_given_rect() {
+        let clip = Rect::new(Point2D::new(10, 10), Size2D::new(2, 2));
+        let mut framebuffer = Framebuffer::new();
+        fixtures::sheet().draw_tile(
+            "block".into(),
+            Point2D::new(9, 9),
+            PalletColor::Color3.into(),
+            Orientation::default(),
+            clip,
+            &mut framebuffer,
+        );
+        assert_eq!(
+            drawn_pixels(&framebuffer),
+            clip.point_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn missing_tiles_draw_the_missing_sprite() {
+        let mut sheet = fixtures::sheet();
+        let draw = |sheet: &SpriteSheet, tile: TileKey| {
+            let mut framebuffer = Framebuffer::new();
+            let size = sheet.draw_tile(
+                tile,
+                Point2D::new(1, 1),
+                ColorMap::default(),
+                Orientation::default(),
+                RENDER_RECT,
+                &mut framebuffer,
+            );
+            (size, drawn_pixels(&framebuffer))
+        };
+
+        // Without a missing sprite nothing is drawn
+        assert_eq!(draw(&sheet, '\u{1f438}'.into()), (Size2D::zero(), vec![]));
+
+        sheet.insert_sprite(
+            MISSING_TILE,
+            SpriteData {
+                size: Size2D::new(2, 1),
+                data: vec![PalletColor::Color2, PalletColor::Color1],
+            },
+        );
+        assert_eq!(
+            draw(&sheet, '\u{1f438}'.into()),
+            (Size2D::new(2, 1), vec![Point2D::new(1, 1)])
+        );
+        draw(&sheet, "nope".into());
+        draw(&sheet, "nope".into());
+
+        // Each missing tile is only remembered once
+        let mut missing: Vec<_> = sheet.missing.lock().unwrap().iter().cloned().collect();
+        missing.sort();
+        assert_eq!(missing, ["nope", "\u{1f438}"]);
+    }
+
+    #[test]
+    fn builtin_assets_load() {
+        let assets = Assets::default();
+        for s in Sheet::ALL {
+            assert!(assets.sheet(s).sprites().next().is_some(), "{s:?} is empty");
+        }
+        assert_eq!(assets.levels.len(), level::builtin_levels().len());
+    }
+
+    #[test]
+    fn characters_fall_back_to_other_case() {
+        let assets = Assets::default();
+        let font = assets.sheet(Sheet::Font);
+        assert!(font.supports('a'));
+        assert!(font.supports('A'));
+        assert!(!font.supports('\u{e9}'));
+        assert!(!font.supported_chars().any(|c| c == 'A'));
+        assert!(font.supported_chars().any(|c| c == '~'));
+
+        let draw = |text: &str| {
+            let mut framebuffer = Framebuffer::new();
+            let bounds = Bounds(Rect::new(Point2D::zero(), Size2D::new(100, 10)));
+            TextBox::new(text, PalletColor::Color2).draw(
+                &bounds,
+                RENDER_RECT,
+                &assets,
+                &mut framebuffer,
+            );
+            drawn_pixels(&framebuffer)
+        };
+        assert_eq!(draw("FROG"), draw("frog"));
+        assert_ne!(draw("\u{e9}"), vec![]);
+    }
+
+    #[test]
+    fn text_partially_off_screen() {
+        let assets = Assets::default();
+        let mut framebuffer = Framebuffer::new();
+        let text = TextBox::new("frog quest battle", PalletColor::Color3);
+        for origin in [
+            Point2D::new(-20, -3),
+            Point2D::new(RENDER_RECT.size.width - 20, RENDER_RECT.size.height - 3),
+        ] {
+            let bounds = Bounds(Rect::new(origin, Size2D::new(100, 10)));
+            text.draw(&bounds, RENDER_RECT, &assets, &mut framebuffer);
+        }
+        assert!(!drawn_pixels(&framebuffer).is_empty());
+    }
+
+    #[test]
+    fn text_is_clipped_to_the_rect_it_was_spawned_in() {
+        use bevy::ecs::system::{System as _, SystemState};
+
+        let mut world = World::new();
+        world.insert_resource(fixtures::assets());
+        world.insert_non_send(Framebuffer::new());
+        let rect = Rect::new(Point2D::new(10, 10), Size2D::new(20, 5));
+        let mut commands_state = SystemState::<Commands>::new(&mut world);
+        let mut commands = commands_state.get_mut(&mut world);
+        TextBox::spawn_in(
+            &mut commands,
+            "aaaa aaaa aaaa aaaa",
+            rect,
+            PalletColor::Color3,
+            Align::Left,
+        );
+        commands_state.apply(&mut world);
+
+        let mut draw = draw_sprites::<TextBox, Framebuffer>.system();
+        draw.initialize(&mut world);
+        draw.run((), &mut world);
+
+        // The first line is drawn, and the ones wrapped below it are cut off
+        let drawn = drawn_pixels(world.get_non_send_resource::<Framebuffer>().unwrap());
+        assert!(drawn.contains(&rect.origin));
+        assert!(drawn.iter().all(|&p| rect.contains(p)), "{drawn:?}");
+    }
+
+    #[test]
+    fn wrapped_sprite_draws_on_both_sides() {
+        let assets = fixtures::assets();
+        let sprite = SimpleSprite {
+            tile: "block".into(),
+            colors: PalletColor::Color3.into(),
+            orientation: Orientation::default(),
+        };
+        let w = RENDER_RECT.size.width;
+
+        for (x, left_columns, right_columns) in [(w - 1, 2, 1), (-2, 1, 2), (10, 3, 0)] {
+            let bounds = Bounds(Rect::new(Point2D::new(x, 5), Size2D::new(3, 3)));
+            let mut framebuffer = Framebuffer::new();
+            sprite.draw(&bounds, RENDER_RECT, &assets, &mut framebuffer);
+            draw_wrapped(&sprite, &bounds, RENDER_RECT, &assets, &mut framebuffer);
+
+            let drawn = drawn_pixels(&framebuffer);
+            let left = drawn.iter().filter(|p| p.x < w / 2).count();
+            let right = drawn.iter().filter(|p| p.x >= w / 2).count();
+            assert_eq!(
+                (left, right),
+                (left_columns * 3, right_columns * 3),
+                "x = {x}"
+            );
+        }
+    }
+
+    #[test]
+    fn multi_color_sprite() {
+        use PalletColor::*;
+
+        let mut sheet = SpriteSheet::default();
+        sheet.insert_sprite(
+            "frog",
+            SpriteData {
+                size: Size2D::new(4, 1),
+                data: vec![Color1, Color2, Color3, Color4],
+            },
+        );
+        let draw = |colors: ColorMap| {
+            let mut framebuffer = Framebuffer::new();
+            framebuffer.color_pixel(Point2D::new(0, 0), Color3);
+            sheet.draw_tile(
+                "frog".into(),
+                Point2D::origin(),
+                colors,
+                Orientation::default(),
+                RENDER_RECT,
+                &mut framebuffer,
+            );
+            framebuffer.pallet_indices()[..4].to_vec()
+        };
+
+        // Color1 is transparent, leaving what was there before
+        assert_eq!(draw(ColorMap::IDENTITY), [Color3, Color2, Color3, Color4]);
+        assert_eq!(
+            draw(ColorMap([Color1, Color4, Color2, Color1])),
+            [Color3, Color4, Color2, Color1]
+        );
+        assert_eq!(
+            draw(ColorMap::tint(Color4)),
+            [Color3, Color4, Color3, Color4]
+        );
+    }
+
+    #[test]
+    fn orientation() {
+        use PalletColor::*;
+
+        // A 3x2 sprite with a different color in each corner
+        //   2 1 3
+        //   1 1 4
+        let mut sheet = SpriteSheet::default();
+        sheet.insert_sprite(
+            "corners",
+            SpriteData {
+                size: Size2D::new(3, 2),
+                data: vec![Color2, Color1, Color3, Color1, Color1, Color4],
+            },
+        );
+        let draw = |flip_x, flip_y, rotation| {
+            let mut framebuffer = Framebuffer::new();
+            let orientation = Orientation {
+                flip_x,
+                flip_y,
+                rotation,
+            };
+            let size = sheet.draw_tile(
+                "corners".into(),
+                Point2D::origin(),
+                ColorMap::IDENTITY,
+                orientation,
+                RENDER_RECT,
+                &mut framebuffer,
+            );
+            let indices = framebuffer.pallet_indices();
+            let rows: Vec<Vec<PalletColor>> = (0..size.height)
+                .map(|y| {
+                    let start = (y * RENDER_RECT.size.width) as usize;
+                    indices[start..(start + size.width as usize)].to_vec()
+                })
+                .collect();
+            rows
+        };
+
+        assert_eq!(
+            draw(false, false, Rotation::None),
+            [[Color2, Color1, Color3], [Color1, Color1, Color4]]
+        );
+        assert_eq!(
+            draw(true, false, Rotation::None),
+            [[Color3, Color1, Color2], [Color4, Color1, Color1]]
+        );
+        assert_eq!(
+            draw(false, true, Rotation::None),
+            [[Color1, Color1, Color4], [Color2, Color1, Color3]]
+        );
+        assert_eq!(
+            draw(false, false, Rotation::Quarter),
+            [[Color1, Color2], [Color1, Color1], [Color4, Color3]]
+        );
+        assert_eq!(
+            draw(false, false, Rotation::Half),
+            [[Color4, Color1, Color1], [Color3, Color1, Color2]]
+        );
+        assert_eq!(
+            draw(false, false, Rotation::ThreeQuarters),
+            [[Color3, Color4], [Color1, Color1], [Color2, Color1]]
+        );
+        // Flipped and then turned
+        assert_eq!(
+            draw(true, false, Rotation::Quarter),
+            [[Color4, Color3], [Color1, Color1], [Color1, Color2]]
+        );
+    }
+}
@@ -1,12 +1,16 @@
 // copyright 2022 Remi Bernotavicius
 
-use super::window;
-use bevy::prelude::*;
 use enumset::EnumSetType;
-use gilrs::ev::{Axis, Button, EventType};
 use std::sync::mpsc::{channel, Receiver, Sender};
-use wasm_bindgen::prelude::*;
-use wasm_bindgen::JsCast as _;
+
+#[cfg(target_arch = "wasm32")]
+use {
+    super::window,
+    bevy::prelude::*,
+    gilrs::ev::{Axis, Button, EventType},
+    wasm_bindgen::prelude::*,
+    wasm_bindgen::JsCast as _,
+};
 
 #[derive(EnumSetType, Debug)]
 pub enum Input {
@@ -15,6 +19,7 @@ pub enum Input {
     Left,
     Right,
     Primary,
+    Secondary,
 }
 
 pub struct InputStream {
@@ -22,6 +27,7 @@ pub struct InputStream {
     recv: Receiver<Input>,
 }
 
+#[cfg(target_arch = "wasm32")]
 fn input_from_keyboard_event(e: &web_sys::KeyboardEvent) -> Option<Input> {
     match e {
         e if e.code() == "ArrowUp" => Some(Input::Up),
@@ -30,10 +36,13 @@ fn input_from_keyboard_event(e: &web_sys::KeyboardEvent) -> Option<Input> {
         e if e.code() == "ArrowRight" => Some(Input::Right),
         e if e.code() == "Enter" => Some(Input::Primary),
         e if e.code() == "Space" => Some(Input::Primary),
+        e if e.code() == "Escape" => Some(Input::Secondary),
+        e if e.code() == "KeyX" => Some(Input::Secondary),
         _ => None,
     }
 }
 
+#[cfg(target_arch = "wasm32")]
 fn keyboard_source(send: Sender<Input>) {
     let window = window();
 
@@ -55,6 +64,7 @@ impl InputStream {
     pub fn new() -> Self {
         let (send, recv) = channel();
 
+        #[cfg(target_arch = "wasm32")]
         keyboard_source(send.clone());
 
         Self { send, recv }
@@ -75,9 +85,11 @@ impl Default for InputStream {
     }
 }
 
+#[cfg(target_arch = "wasm32")]
 fn input_from_controller_button(button: gilrs::ev::Button) -> Option<Input> {
     match button {
         Button::East => Some(Input::Primary),
+        Button::South => Some(Input::Secondary),
         Button::DPadUp => Some(Input::Up),
         Button::DPadDown => Some(Input::Down),
         Button::DPadLeft => Some(Input::Left),
@@ -86,6 +98,7 @@ fn input_from_controller_button(button: gilrs::ev::Button) -> Option<Input> {
     }
 }
 
+#[cfg(target_arch = "wasm32")]
 fn drive_controller(mut input_stream: NonSendMut<InputStream>, mut grs: NonSendMut<gilrs::Gilrs>) {
     while let Some(event) = grs.next_event() {
         match event.event {
@@ -111,8 +124,10 @@ fn drive_controller(mut input_stream: NonSendMut<InputStream>, mut grs: NonSendM
     }
 }
 
+#[cfg(target_arch = "wasm32")]
 pub struct Plugin;
 
+#[cfg(target_arch = "wasm32")]
 impl bevy::app::Plugin for Plugin {
     fn build(&self, app: &mut App) {
         app.init_non_send_resource::<InputStream>()
@@ -0,0 +1,281 @@
+// copyright 2022 Remi Bernotavicius
+
+//! The arenas matches are played in. A `Level` lists its platforms, hazards and where players
+//! start, along with the tiles drawn behind everything and optionally the pallet to show it in.
+//!
+//! Level files are written by `cargo run --example level_compiler` from a text description, see
+//! `src/level_compiler.rs`. A file is laid out as described in `file_format`, with the magic bytes
+//! `FQBL`.
+
+use super::file_format::{self, FileError, FileErrorKind, FileFormat};
+use super::graphics::{Assets, Bounds, PalletPreset, PointIterExt as _, Sprite};
+use super::platform::PlatformKind;
+use super::renderer::{PalletColor, Pixels, Renderer};
+use bevy::ecs::system::EntityCommands;
+use bevy::prelude::*;
+use euclid::{Point2D, Rect};
+use serde::{Deserialize, Serialize};
+
+/// `Level::to_bytes` writes the `version` here. Bump it when changing `Level`, and teach
+/// `Level::from_bytes` to read the previous version.
+const FORMAT: FileFormat = FileFormat {
+    name: "level",
+    magic: b"FQBL",
+    version: 1,
+};
+
+/// The width and height of each cell of a level's `Background`
+pub const TILE_SIZE: i32 = 8;
+
+/// The file name in `assets/levels/` and contents of each level built in to the game
+pub const BUILTIN: [(&str, &[u8]); 3] = [
+    ("pond", include_bytes!("../assets/levels/pond.bin")),
+    ("logjam", include_bytes!("../assets/levels/logjam.bin")),
+    ("quarry", include_bytes!("../assets/levels/quarry.bin")),
+];
+
+/// Touching a hazard knocks a player out
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HazardKind {
+    Water,
+    Thorns,
+}
+
+impl HazardKind {
+    pub const ALL: [Self; 2] = [Self::Water, Self::Thorns];
+
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Water => "water",
+            Self::Thorns => "thorns",
+        }
+    }
+
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.name() == name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LevelPlatform {
+    pub kind: PlatformKind,
+    pub rect: Rect<i32, Pixels>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LevelHazard {
+    pub kind: HazardKind,
+    pub rect: Rect<i32, Pixels>,
+}
+
+/// A hazard in the arena, knocking out any player who touches its `Bounds`
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Hazard {
+    pub kind: HazardKind,
+}
+
+impl Hazard {
+    pub fn spawn<'a, 'w, 's>(
+        commands: &'a mut Commands<'w, 's>,
+        kind: HazardKind,
+        rect: Rect<i32, Pixels>,
+    ) -> EntityCommands<'w, 's, 'a> {
+        let mut entity = commands.spawn();
+        entity.insert(Self { kind }).insert(Bounds(rect));
+        entity
+    }
+}
+
+impl Sprite for Hazard {
+    fn draw(
+        &self,
+        bounds: &Bounds,
+        clip: Rect<i32, Pixels>,
+        _assets: &Assets,
+        renderer: &mut impl Renderer,
+    ) {
+        if let Some(visible) = bounds.0.intersection(&clip) {
+            for p in visible.point_iter() {
+                let (x, y) = (p.x - bounds.0.min_x(), p.y - bounds.0.min_y());
+                let color = match self.kind {
+                    // A rippling surface over deep water
+                    HazardKind::Water if y == 0 || (y == 1 && x % 6 < 3) => PalletColor::Color3,
+                    HazardKind::Water => PalletColor::Color2,
+                    // Rows of spikes, each narrowing to a point at the top
+                    HazardKind::Thorns if (x % 6 - 2).abs() <= y / 2 => PalletColor::Color4,
+                    HazardKind::Thorns => continue,
+                };
+                renderer.color_pixel(p, color);
+            }
+        }
+    }
+}
+
+/// A grid of `TILE_SIZE` cells starting at the top left of the screen. Each character of a row is a
+/// cell, and is either '.' for an empty cell or one of the `tiles`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Background {
+    /// Each character used in `rows` and the name of the sprite it stands for
+    pub tiles: Vec<(char, String)>,
+    pub rows: Vec<String>,
+}
+
+impl Background {
+    /// The name of the sprite for the character, or `None` for an empty cell
+    pub fn tile(&self, c: char) -> Option<&str> {
+        self.tiles
+            .iter()
+            .find(|&&(t, _)| t == c)
+            .map(|(_, name)| name.as_str())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Level {
+    pub name: String,
+    /// Where each player starts, and comes back after being knocked out. Players beyond the number
+    /// of spawn points go back to the first ones.
+    pub spawns: Vec<Point2D<i32, Pixels>>,
+    pub platforms: Vec<LevelPlatform>,
+    pub hazards: Vec<LevelHazard>,
+    pub background: Background,
+    /// The pallet the level is shown in, unless the player has chosen a pallet other than the
+    /// classic one
+    pub pallet: Option<PalletPreset>,
+}
+
+impl Level {
+    pub fn spawn_point(&self, handle: u32) -> Point2D<i32, Pixels> {
+        self.spawns[handle as usize % self.spawns.len()]
+    }
+
+    /// Checks the level makes sense, the things `Level::from_bytes` can't tell from decoding alone
+    pub fn validate(&self) -> Result<(), String> {
+        if self.spawns.is_empty() {
+            return Err("level has no spawn points".into());
+        }
+        let rects = self
+            .platforms
+            .iter()
+            .map(|p| p.rect)
+            .chain(self.hazards.iter().map(|h| h.rect));
+        for rect in rects {
+            if rect.is_empty() {
+                return Err(format!("{rect:?} is empty"));
+            }
+        }
+        for (i, row) in self.background.rows.iter().enumerate() {
+            if let Some(c) = row
+                .chars()
+                .find(|&c| c != '.' && self.background.tile(c).is_none())
+            {
+                return Err(format!("background row {} uses unknown tile {c:?}", i + 1));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Level {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FileError> {
+        // Every version so far is encoded the same way
+        let (_version, body) = FORMAT.read(bytes)?;
+        let level: Self = FORMAT.decode(body)?;
+        level
+            .validate()
+            .map_err(|e| FORMAT.error(FileErrorKind::Invalid(e)))?;
+        Ok(level)
+    }
+
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        FORMAT.write(self)
+    }
+
+    /// The checksum of the encoded level, the same as in the header of its file. Peers compare it
+    /// to make sure they play on the same level, even after one has reloaded it.
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    pub fn checksum(&self) -> u32 {
+        file_format::checksum(&bincode::serialize(self).unwrap())
+    }
+}
+
+/// The levels built in to the game, in the order they are chosen from
+pub fn builtin_levels() -> Vec<Level> {
+    BUILTIN
+        .iter()
+        .map(|(_, bytes)| Level::from_bytes(bytes).expect("the built in levels are valid"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures;
+    use super::*;
+
+    #[test]
+    fn level_round_trip() {
+        let level = fixtures::level();
+        assert_eq!(Level::from_bytes(&level.to_bytes()).unwrap(), level);
+    }
+
+    #[test]
+    fn rejects_bad_levels() {
+        let mut bytes = fixtures::level().to_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(matches!(
+            Level::from_bytes(&bytes),
+            Err(FileError {
+                kind: FileErrorKind::ChecksumMismatch { .. },
+                ..
+            })
+        ));
+        assert!(matches!(
+            Level::from_bytes(&bytes[..6]),
+            Err(FileError {
+                kind: FileErrorKind::Truncated,
+                ..
+            })
+        ));
+
+        let mut level = fixtures::level();
+        level.background.rows.push("..x".into());
+        assert_eq!(
+            Level::from_bytes(&level.to_bytes())
+                .err()
+                .unwrap()
+                .to_string(),
+            "invalid level: background row 3 uses unknown tile 'x'"
+        );
+
+        let mut level = fixtures::level();
+        level.spawns.clear();
+        assert!(matches!(
+            Level::from_bytes(&level.to_bytes()),
+            Err(FileError {
+                kind: FileErrorKind::Invalid(_),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn spawn_points_repeat() {
+        let level = fixtures::level();
+        assert_eq!(level.spawn_point(1), Point2D::new(3, 4));
+        assert_eq!(level.spawn_point(2), Point2D::new(1, 2));
+    }
+
+    #[test]
+    fn builtin_levels_load() {
+        for (name, bytes) in BUILTIN {
+            let level = Level::from_bytes(bytes)
+                .unwrap_or_else(|e| panic!("assets/levels/{name}.bin: {e}"));
+            assert_eq!(level.name, name);
+        }
+        assert_eq!(builtin_levels().len(), BUILTIN.len());
+    }
+}
@@ -0,0 +1,213 @@
+// copyright 2022 Remi Bernotavicius
+
+//! Builds `Level` files from a text description, so arenas can be made without changing the game.
+//! A description looks like:
+//!
+//! ```text
+//! # comments start with '#'
+//! name pond
+//! pallet game boy
+//! spawn 10 10
+//! spawn 354 10
+//! platform lily_pad 30 120 60 4
+//! platform log 140 170 104 8
+//! hazard water 0 206 384 10
+//! tile ~ water
+//! row ........~~~~
+//! row .......~~~~~
+//! ```
+//!
+//! `name` is what the level is called in the menu and `pallet` optionally picks the pallet it is
+//! shown in. `spawn` adds the point the next player starts at. `platform` and `hazard` take a kind
+//! followed by the x, y, width and height of their rectangle. Platforms are `lily_pad`, which can
+//! be jumped up through, `log` or `rock`, and hazards are `water` or `thorns`.
+//!
+//! The background is drawn from `row`s of tiles, top to bottom, each `TILE_SIZE` pixels square.
+//! `tile` says which sprite of the tiles sheet a character stands for, and '.' leaves a cell empty.
+
+use super::graphics::PalletPreset;
+use super::level::{HazardKind, Level, LevelHazard, LevelPlatform};
+use super::platform::PlatformKind;
+use super::renderer::Pixels;
+use euclid::{Point2D, Rect, Size2D};
+use std::io;
+use std::path::Path;
+
+fn parse_int(word: Option<&str>, what: &str) -> Result<i32, String> {
+    let word = word.ok_or_else(|| format!("missing {what}"))?;
+    word.parse().map_err(|_| format!("invalid {what} {word:?}"))
+}
+
+fn parse_rect<'a>(words: &mut impl Iterator<Item = &'a str>) -> Result<Rect<i32, Pixels>, String> {
+    Ok(Rect::new(
+        Point2D::new(parse_int(words.next(), "x")?, parse_int(words.next(), "y")?),
+        Size2D::new(
+            parse_int(words.next(), "width")?,
+            parse_int(words.next(), "height")?,
+        ),
+    ))
+}
+
+fn parse_line(line: &str, level: &mut Level) -> Result<(), String> {
+    let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let rest = rest.trim();
+    let mut words = rest.split_whitespace();
+    match keyword {
+        "name" => level.name = rest.into(),
+        "pallet" => {
+            let preset =
+                PalletPreset::from_name(rest).ok_or_else(|| format!("unknown pallet {rest:?}"))?;
+            level.pallet = Some(preset);
+        }
+        "spawn" => {
+            level.spawns.push(Point2D::new(
+                parse_int(words.next(), "x")?,
+                parse_int(words.next(), "y")?,
+            ));
+        }
+        "platform" => {
+            let name = words.next().unwrap_or_default();
+            let kind = PlatformKind::from_name(name)
+                .ok_or_else(|| format!("unknown platform {name:?}"))?;
+            let rect = parse_rect(&mut words)?;
+            level.platforms.push(LevelPlatform { kind, rect });
+        }
+        "hazard" => {
+            let name = words.next().unwrap_or_default();
+            let kind =
+                HazardKind::from_name(name).ok_or_else(|| format!("unknown hazard {name:?}"))?;
+            let rect = parse_rect(&mut words)?;
+            level.hazards.push(LevelHazard { kind, rect });
+        }
+        "tile" => {
+            let mut chars = words.next().unwrap_or_default().chars();
+            let c = match (chars.next(), chars.next()) {
+                (Some(c), None) if c != '.' => c,
+                _ => return Err("tile must be a single character other than '.'".into()),
+            };
+            let sprite = words.next().ok_or("missing tile sprite")?;
+            level.background.tiles.push((c, sprite.into()));
+        }
+        "row" => {
+            level
+                .background
+                .rows
+                .push(words.next().unwrap_or_default().into());
+        }
+        keyword => return Err(format!("unknown keyword {keyword:?}")),
+    }
+    if !matches!(keyword, "name" | "pallet") {
+        if let Some(extra) = words.next() {
+            return Err(format!("unexpected {extra:?}"));
+        }
+    }
+    Ok(())
+}
+
+fn parse_level(text: &str) -> Result<Level, String> {
+    let mut level = Level {
+        name: String::new(),
+        spawns: vec![],
+        platforms: vec![],
+        hazards: vec![],
+        background: Default::default(),
+        pallet: None,
+    };
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        parse_line(line, &mut level).map_err(|e| format!("line {}: {e}", n + 1))?;
+    }
+    if level.name.is_empty() {
+        return Err("level has no name".into());
+    }
+    level.validate()?;
+    Ok(level)
+}
+
+fn load_level(source_path: &Path) -> io::Result<Level> {
+    parse_level(&std::fs::read_to_string(source_path)?).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {e}", source_path.display()),
+        )
+    })
+}
+
+/// Builds a `Level` file from the description in the module documentation
+pub fn compile_level(source_path: &Path, output_path: &Path) -> io::Result<()> {
+    let level = load_level(source_path)?;
+    std::fs::write(output_path, level.to_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_level_description() {
+        let level = parse_level(
+            "# a comment\n\
+             name the pond\n\
+             pallet game boy\n\
+             spawn 10 20\n\
+             platform lily_pad 1 2 3 4\n\
+             hazard thorns 5 6 7 8\n\
+             tile ~ water\n\
+             row ..~~\n",
+        )
+        .unwrap();
+        assert_eq!(level.name, "the pond");
+        assert_eq!(level.pallet, Some(PalletPreset::GameBoy));
+        assert_eq!(level.spawns, [Point2D::new(10, 20)]);
+        assert_eq!(
+            level.platforms,
+            [LevelPlatform {
+                kind: PlatformKind::LilyPad,
+                rect: Rect::new(Point2D::new(1, 2), Size2D::new(3, 4)),
+            }]
+        );
+        assert_eq!(
+            level.hazards,
+            [LevelHazard {
+                kind: HazardKind::Thorns,
+                rect: Rect::new(Point2D::new(5, 6), Size2D::new(7, 8)),
+            }]
+        );
+        assert_eq!(level.background.tile('~'), Some("water"));
+        assert_eq!(level.background.rows, ["..~~"]);
+    }
+
+    #[test]
+    fn parse_level_errors() {
+        let error = |text| parse_level(text).err().unwrap();
+        assert_eq!(error("spawn 0 0\n"), "level has no name");
+        assert_eq!(error("name a\n"), "level has no spawn points");
+        assert_eq!(
+            error("name a\nplatform cloud 0 0 1 1\n"),
+            "line 2: unknown platform \"cloud\""
+        );
+        assert_eq!(
+            error("name a\nspawn 0 0\nhazard water 0 0 1\n"),
+            "line 3: missing height"
+        );
+        assert_eq!(error("name a\nspawn 0 0 0\n"), "line 2: unexpected \"0\"");
+        assert_eq!(
+            error("name a\nspawn 0 0\nrow ..#\n"),
+            "background row 1 uses unknown tile '#'"
+        );
+    }
+
+    #[test]
+    fn checked_in_sources_match_assets() {
+        use super::super::level::BUILTIN;
+
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/levels");
+        for (name, bytes) in BUILTIN {
+            let compiled = load_level(&root.join(format!("{name}.txt"))).unwrap();
+            assert_eq!(compiled, Level::from_bytes(bytes).unwrap());
+        }
+    }
+}
@@ -4,22 +4,136 @@ use bevy::app::ScheduleRunnerSettings;
 use bevy::prelude::*;
 use bevy::utils::Duration;
 use input::InputStream;
-use renderer::{CanvasRenderer, RENDER_RECT};
-use wasm_bindgen::prelude::*;
-use wasm_bindgen::JsCast as _;
 
+#[cfg(target_arch = "wasm32")]
+use {
+    renderer::RENDER_RECT, wasm_bindgen::prelude::*, wasm_bindgen::JsCast as _,
+    webgl::CanvasRenderer,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use {renderer::Framebuffer, script::InputScript};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use level_compiler::compile_level;
+#[cfg(not(target_arch = "wasm32"))]
+pub use sprite_compiler::{compile_sprite_sheet, dump_sprite_sheet};
+
+mod animation;
+mod capture;
+mod editor;
+mod file_format;
+#[cfg(test)]
+mod fixtures;
 mod game;
 mod graphics;
 mod input;
+mod level;
+#[cfg(not(target_arch = "wasm32"))]
+mod level_compiler;
+mod loader;
 mod local;
 mod menu;
+#[cfg(target_arch = "wasm32")]
 mod net;
+mod platform;
 mod renderer;
+mod results;
+mod rules;
+#[cfg(not(target_arch = "wasm32"))]
+mod script;
+mod sheet_file;
+#[cfg(test)]
+mod snapshot;
+#[cfg(not(target_arch = "wasm32"))]
+mod sprite_compiler;
+mod text;
+mod tilemap;
+#[cfg(target_arch = "wasm32")]
+mod webgl;
 
+#[cfg(target_arch = "wasm32")]
 fn window() -> web_sys::Window {
     web_sys::window().expect("no global `window` exists")
 }
 
+/// The value of the given query parameter in the page URL, e.g. "full" for `upload` in
+/// `?upload=full`. Parameters without a value, like `?watch_assets`, are the empty string.
+#[cfg(target_arch = "wasm32")]
+fn url_param(name: &str) -> Option<String> {
+    let search = window().location().search().ok()?;
+    search
+        .trim_start_matches('?')
+        .split('&')
+        .find_map(|param| match param.split_once('=') {
+            Some((n, value)) if n == name => Some(value.to_owned()),
+            None if param == name => Some(String::new()),
+            _ => None,
+        })
+}
+
+/// Offers the given bytes to the user as a file download
+#[cfg(target_arch = "wasm32")]
+fn download(window: &web_sys::Window, bytes: &[u8]) -> Result<(), JsValue> {
+    let u8_array = js_sys::Uint8Array::new_with_length(bytes.len() as u32);
+    u8_array.copy_from(bytes);
+    let array = js_sys::Array::new_with_length(1);
+    array.set(0, u8_array.buffer().into());
+    let blob = web_sys::Blob::new_with_buffer_source_sequence_and_options(
+        &array,
+        web_sys::BlobPropertyBag::new().type_("application/octet-stream"),
+    )?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob)?;
+    window.location().set_href(&url)?;
+
+    Ok(())
+}
+
+/// Saves the file, downloading it in the browser and writing it to the current directory natively
+#[cfg(target_arch = "wasm32")]
+fn save_file(name: &str, bytes: &[u8]) {
+    if let Err(e) = download(&window(), bytes) {
+        log::error!("failed to download {name}: {e:?}");
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn save_file(name: &str, bytes: &[u8]) {
+    match std::fs::write(name, bytes) {
+        Ok(()) => log::info!("saved {name}"),
+        Err(e) => log::error!("failed to save {name}: {e}"),
+    }
+}
+
+/// Returns the value saved with `save_setting`, if any
+#[cfg(target_arch = "wasm32")]
+fn load_setting(name: &str) -> Option<String> {
+    window().local_storage().ok()??.get_item(name).ok()?
+}
+
+/// Remembers the value in the browser's local storage, so it is there next time the game is loaded
+#[cfg(target_arch = "wasm32")]
+fn save_setting(name: &str, value: &str) {
+    let saved = window()
+        .local_storage()
+        .ok()
+        .flatten()
+        .is_some_and(|s| s.set_item(name, value).is_ok());
+    if !saved {
+        log::warn!("unable to save setting {name}");
+    }
+}
+
+/// Natively runs are reproducible, so settings always start out as the defaults.
+#[cfg(not(target_arch = "wasm32"))]
+fn load_setting(_name: &str) -> Option<String> {
+    None
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn save_setting(_name: &str, _value: &str) {}
+
+#[cfg(target_arch = "wasm32")]
 fn canvas() -> web_sys::HtmlCanvasElement {
     let document = window().document().unwrap();
     let canvas = document.get_element_by_id("canvas").unwrap();
@@ -29,9 +143,10 @@ fn canvas() -> web_sys::HtmlCanvasElement {
         .unwrap()
 }
 
+#[cfg(target_arch = "wasm32")]
 fn resize_canvas() {
     let canvas = canvas();
-    let canvas_rect = RENDER_RECT * renderer::PIXEL_SCALE;
+    let canvas_rect = RENDER_RECT * webgl::PIXEL_SCALE;
     canvas.set_width(canvas_rect.size.width as u32);
     canvas.set_height(canvas_rect.size.height as u32);
 }
@@ -40,10 +155,14 @@ fn resize_canvas() {
 enum AppState {
     #[default]
     Menu,
+    #[cfg(target_arch = "wasm32")]
     MultiplayerGame,
     SinglePlayerGame,
+    SpriteEditor,
+    Results,
 }
 
+#[cfg(target_arch = "wasm32")]
 #[wasm_bindgen(start)]
 pub fn start() {
     console_error_panic_hook::set_once();
@@ -62,13 +181,62 @@ pub fn start() {
         .add_plugin(input::Plugin)
         .add_plugin(net::Plugin)
         .add_plugin(local::Plugin)
-        .add_plugin(graphics::Plugin)
+        .add_plugin(graphics::Plugin::<CanvasRenderer>::new())
+        .add_plugin(loader::Plugin)
+        .add_plugin(capture::Plugin::<CanvasRenderer>::new())
         .add_plugin(menu::Plugin)
-        .add_plugin(game::Plugin::new(AppState::MultiplayerGame))
-        .add_plugin(game::Plugin::new(AppState::SinglePlayerGame))
+        .add_plugin(results::Plugin)
+        .add_plugin(editor::Plugin::<CanvasRenderer>::new())
+        .add_plugin(game::Plugin::<CanvasRenderer>::new(
+            AppState::MultiplayerGame,
+        ))
+        .add_plugin(game::Plugin::<CanvasRenderer>::new(
+            AppState::SinglePlayerGame,
+        ))
         .run();
 }
 
+/// Builds the game without using any browser APIs. Frames are drawn into a `Framebuffer` and input
+/// comes from the given script.
+#[cfg(not(target_arch = "wasm32"))]
+fn headless_app(state: AppState, script: InputScript) -> App {
+    let mut app = App::new();
+    app.init_non_send_resource::<Framebuffer>()
+        .init_non_send_resource::<InputStream>()
+        .insert_non_send_resource(script)
+        .insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_millis(16)))
+        .add_state(state)
+        .add_plugins(MinimalPlugins)
+        .add_system_to_stage(CoreStage::PreUpdate, InputScript::drive)
+        .add_plugin(bevy_ggrs::GGRSPlugin)
+        .add_plugin(local::Plugin)
+        .add_plugin(graphics::Plugin::<Framebuffer>::new())
+        .add_plugin(loader::Plugin)
+        .add_plugin(capture::Plugin::<Framebuffer>::new())
+        .add_plugin(menu::Plugin)
+        .add_plugin(results::Plugin)
+        .add_plugin(editor::Plugin::<Framebuffer>::new())
+        .add_plugin(game::Plugin::<Framebuffer>::new(AppState::SinglePlayerGame));
+    app
+}
+
+/// Runs a single player game natively, reading one frame of input per line from the given script
+/// file, or from stdin if there is none. Exits when the script is finished.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn run_headless(script_path: Option<&std::path::Path>) -> std::io::Result<()> {
+    env_logger::init();
+
+    log::info!("Frog Quest Battle Starting (headless)");
+
+    let script = match script_path {
+        Some(path) => InputScript::from_lines(std::fs::read_to_string(path)?.lines())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
+        None => InputScript::from_stdin(),
+    };
+    headless_app(AppState::SinglePlayerGame, script).run();
+    Ok(())
+}
+
 fn despawn_screen<T: Component>(to_despawn: Query<Entity, With<T>>, mut commands: Commands) {
     for entity in to_despawn.iter() {
         commands.entity(entity).despawn_recursive();
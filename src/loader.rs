@@ -0,0 +1,397 @@
+// copyright 2022 Remi Bernotavicius
+
+//! Loads assets while the game is running, replacing the ones built in to the game. Natively the
+//! `FROG_ASSETS` environment variable is the directory to load them from, and in the browser it is
+//! the `assets` query parameter in the page URL, a URL relative to the page, e.g.
+//! `?assets=assets/`.
+//!
+//! When also given `FROG_WATCH_ASSETS=1` or `&watch_assets`, the files are checked for changes
+//! every `WATCH_INTERVAL` frames, and any which changed are loaded again. This lets sprites be
+//! edited without rebuilding the game.
+
+use super::graphics::{Assets, PalletPreset, Sheet, SpriteSheet};
+use super::level::{self, Level};
+use super::renderer::{Color, Pallet};
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+#[cfg(target_arch = "wasm32")]
+use {wasm_bindgen::JsCast as _, wasm_bindgen_futures::JsFuture};
+
+/// How many frames to wait between checking files for changes
+const WATCH_INTERVAL: u32 = 30;
+
+/// A file which can be loaded in to `Assets`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum AssetFile {
+    Sheet(Sheet),
+    /// Replacement colors for some `PalletPreset`s
+    Pallets,
+    /// One of the `level::BUILTIN` levels
+    Level(usize),
+}
+
+impl AssetFile {
+    fn all() -> impl Iterator<Item = Self> {
+        [
+            Self::Sheet(Sheet::Font),
+            Self::Sheet(Sheet::Frog),
+            Self::Sheet(Sheet::Tiles),
+            Self::Pallets,
+        ]
+        .into_iter()
+        .chain((0..level::BUILTIN.len()).map(Self::Level))
+    }
+
+    fn name(self) -> String {
+        match self {
+            Self::Sheet(sheet) => format!("{}.bin", sheet.name()),
+            Self::Pallets => "pallets.txt".into(),
+            Self::Level(index) => format!("levels/{}.bin", level::BUILTIN[index].0),
+        }
+    }
+
+    fn apply(self, bytes: &[u8], assets: &mut Assets) -> Result<(), String> {
+        match self {
+            Self::Sheet(sheet) => assets.replace_sheet(
+                sheet,
+                SpriteSheet::from_bytes(bytes).map_err(|e| e.to_string())?,
+            ),
+            Self::Pallets => {
+                let text = std::str::from_utf8(bytes).map_err(|e| e.to_string())?;
+                assets.replace_pallets(parse_pallets(text)?);
+            }
+            Self::Level(index) => {
+                assets.replace_level(index, Level::from_bytes(bytes).map_err(|e| e.to_string())?)
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_color(hex: &str) -> Result<Color, String> {
+    let error = || format!("invalid color {hex:?}");
+    if hex.len() != 6 {
+        return Err(error());
+    }
+    let rgb = u32::from_str_radix(hex, 16).map_err(|_| error())?;
+    Ok(Color {
+        r: (rgb >> 16) as u8,
+        g: (rgb >> 8) as u8,
+        b: rgb as u8,
+    })
+}
+
+/// Each line of a pallets file is four hex colors followed by the name of the preset they replace,
+/// e.g. `0f380f 306230 8bac0f cadc9f game boy`. Lines starting with '#' are comments.
+fn parse_pallets(text: &str) -> Result<HashMap<PalletPreset, Pallet>, String> {
+    let mut pallets = HashMap::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |e| format!("line {}: {e}", n + 1);
+        let mut words = line.splitn(5, char::is_whitespace);
+        let mut colors = [Color::BLACK; 4];
+        for color in &mut colors {
+            *color = parse_color(words.next().unwrap_or_default()).map_err(error)?;
+        }
+        let name = words.next().unwrap_or_default().trim();
+        let preset = PalletPreset::from_name(name)
+            .ok_or_else(|| error(format!("unknown pallet {name:?}")))?;
+        pallets.insert(preset, Pallet(colors));
+    }
+    Ok(pallets)
+}
+
+/// Where assets are loaded from
+struct AssetConfig {
+    /// The directory or URL the asset file names are relative to
+    base: String,
+    watch: bool,
+}
+
+impl AssetConfig {
+    #[cfg(not(target_arch = "wasm32"))]
+    fn from_environment() -> Option<Self> {
+        let mut base = std::env::var("FROG_ASSETS").ok()?;
+        if !base.ends_with('/') {
+            base.push('/');
+        }
+        Some(Self {
+            base,
+            watch: std::env::var_os("FROG_WATCH_ASSETS").is_some(),
+        })
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn from_environment() -> Option<Self> {
+        let mut base = super::url_param("assets")?;
+        if !base.is_empty() && !base.ends_with('/') {
+            base.push('/');
+        }
+        Some(Self {
+            base,
+            watch: super::url_param("watch_assets").is_some(),
+        })
+    }
+}
+
+/// The contents of a file, or `None` if it doesn't exist. Missing files aren't an error, since not
+/// every asset has to be replaced.
+type Fetched = (AssetFile, Result<Option<Vec<u8>>, String>);
+
+#[cfg(not(target_arch = "wasm32"))]
+fn fetch(file: AssetFile, path: String, sender: Sender<Fetched>) {
+    let result = match std::fs::read(&path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{path}: {e}")),
+    };
+    let _ = sender.send((file, result));
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn fetch_bytes(url: &str) -> Result<Option<Vec<u8>>, wasm_bindgen::JsValue> {
+    let mut init = web_sys::RequestInit::new();
+    init.cache(web_sys::RequestCache::NoStore);
+    let response: web_sys::Response =
+        JsFuture::from(super::window().fetch_with_str_and_init(url, &init))
+            .await?
+            .dyn_into()?;
+    if response.status() == 404 {
+        return Ok(None);
+    }
+    if !response.ok() {
+        return Err(format!("{url}: {}", response.status_text()).into());
+    }
+    let buffer = JsFuture::from(response.array_buffer()?).await?;
+    Ok(Some(js_sys::Uint8Array::new(&buffer).to_vec()))
+}
+
+/// Downloads the file in the background
+#[cfg(target_arch = "wasm32")]
+fn fetch(file: AssetFile, url: String, sender: Sender<Fetched>) {
+    wasm_bindgen_futures::spawn_local(async move {
+        let result = fetch_bytes(&url).await.map_err(|e| format!("{url}: {e:?}"));
+        let _ = sender.send((file, result));
+    });
+}
+
+/// Fetches the asset files and puts them in `Assets` as they arrive
+pub struct AssetLoader {
+    config: AssetConfig,
+    sender: Sender<Fetched>,
+    recv: Receiver<Fetched>,
+    /// Files which were requested and haven't arrived yet
+    pending: HashSet<AssetFile>,
+    /// The contents of each file the last time it was fetched, to tell when it changes
+    loaded: HashMap<AssetFile, Vec<u8>>,
+    started: bool,
+    frames_until_watch: u32,
+}
+
+impl AssetLoader {
+    fn new(config: AssetConfig) -> Self {
+        let (sender, recv) = channel();
+        Self {
+            config,
+            sender,
+            recv,
+            pending: HashSet::new(),
+            loaded: HashMap::new(),
+            started: false,
+            frames_until_watch: 0,
+        }
+    }
+
+    fn fetch_all(&mut self) {
+        for file in AssetFile::all() {
+            if self.pending.insert(file) {
+                let path = format!("{}{}", self.config.base, file.name());
+                fetch(file, path, self.sender.clone());
+            }
+        }
+    }
+
+    /// Puts a fetched file in `Assets`, unless it is the same as last time. Returns whether it was
+    /// replaced.
+    fn receive(
+        &mut self,
+        file: AssetFile,
+        bytes: Vec<u8>,
+        assets: &mut Assets,
+    ) -> Result<bool, String> {
+        if self.loaded.get(&file) == Some(&bytes) {
+            return Ok(false);
+        }
+        let result = file.apply(&bytes, assets);
+        // Remember bad files too, so the error is only reported once per change
+        self.loaded.insert(file, bytes);
+        result.map(|()| true)
+    }
+
+    fn update(mut self_: NonSendMut<Self>, mut assets: ResMut<Assets>) {
+        if !self_.started || self_.config.watch && self_.frames_until_watch == 0 {
+            self_.started = true;
+            self_.frames_until_watch = WATCH_INTERVAL;
+            self_.fetch_all();
+        }
+        self_.frames_until_watch = self_.frames_until_watch.saturating_sub(1);
+
+        while let Ok((file, result)) = self_.recv.try_recv() {
+            self_.pending.remove(&file);
+            let name = file.name();
+            let result = match result {
+                Ok(Some(bytes)) => self_.receive(file, bytes, &mut assets),
+                Ok(None) => Ok(false),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(true) => log::info!("loaded {name}"),
+                Ok(false) => {}
+                Err(e) => log::warn!("failed to load {name}: {e}"),
+            }
+        }
+    }
+}
+
+/// Adds an `AssetLoader` if assets have been configured to load at runtime
+pub struct Plugin;
+
+impl bevy::app::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        if let Some(config) = AssetConfig::from_environment() {
+            log::info!(
+                "loading assets from {:?}, watching for changes: {}",
+                config.base,
+                config.watch
+            );
+            app.insert_non_send_resource(AssetLoader::new(config))
+                .add_system_to_stage(CoreStage::PreUpdate, AssetLoader::update);
+        }
+    }
+
+    fn name(&self) -> &str {
+        "loader"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures;
+    use super::*;
+
+    fn loader() -> AssetLoader {
+        AssetLoader::new(AssetConfig {
+            base: String::new(),
+            watch: true,
+        })
+    }
+
+    #[test]
+    fn parse_pallet_file() {
+        let pallets = parse_pallets(
+            "# comment\n\
+             \n\
+             000000 112233 aabbcc FFFFFF high contrast\n",
+        )
+        .unwrap();
+        let c = |r, g, b| Color { r, g, b };
+        assert_eq!(
+            pallets,
+            HashMap::from([(
+                PalletPreset::HighContrast,
+                Pallet([
+                    c(0, 0, 0),
+                    c(0x11, 0x22, 0x33),
+                    c(0xaa, 0xbb, 0xcc),
+                    c(255, 255, 255)
+                ])
+            )])
+        );
+
+        assert_eq!(
+            parse_pallets("000000 000000 000000 00000 classic").unwrap_err(),
+            "line 1: invalid color \"00000\""
+        );
+        assert_eq!(
+            parse_pallets("000000 000000 000000 000000 sepia").unwrap_err(),
+            "line 1: unknown pallet \"sepia\""
+        );
+    }
+
+    #[test]
+    fn replaces_assets_when_files_change() {
+        let mut loader = loader();
+        let mut assets = Assets::default();
+        let file = AssetFile::Pallets;
+        let text = b"000000 000000 000000 000000 classic".to_vec();
+
+        assert_eq!(loader.receive(file, text.clone(), &mut assets), Ok(true));
+        assert_eq!(
+            assets.pallet(PalletPreset::Classic),
+            Pallet([Color::BLACK; 4])
+        );
+
+        // Fetching the same file again does nothing
+        assert_eq!(loader.receive(file, text, &mut assets), Ok(false));
+
+        // A bad file leaves the assets as they were, and is only reported once
+        assert!(loader.receive(file, b"bad".to_vec(), &mut assets).is_err());
+        assert_eq!(
+            loader.receive(file, b"bad".to_vec(), &mut assets),
+            Ok(false)
+        );
+        assert_eq!(
+            assets.pallet(PalletPreset::Classic),
+            Pallet([Color::BLACK; 4])
+        );
+    }
+
+    #[test]
+    fn loads_sprite_sheets() {
+        let mut loader = loader();
+        let mut assets = Assets::default();
+        let sheet = Sheet::Frog;
+        let bytes = fixtures::sheet().to_bytes();
+
+        assert_eq!(
+            loader.receive(AssetFile::Sheet(sheet), bytes, &mut assets),
+            Ok(true)
+        );
+        assert!(assets
+            .sheet(sheet)
+            .sprites()
+            .any(|(name, _)| name == "block"));
+
+        assert!(loader
+            .receive(AssetFile::Sheet(sheet), b"FQBS".to_vec(), &mut assets)
+            .is_err());
+        assert!(assets
+            .sheet(sheet)
+            .sprites()
+            .any(|(name, _)| name == "block"));
+    }
+
+    #[test]
+    fn loads_levels() {
+        let mut loader = loader();
+        let mut assets = Assets::default();
+        let file = AssetFile::Level(1);
+        assert_eq!(file.name(), "levels/logjam.bin");
+
+        let mut level = assets.level(0).clone();
+        level.name = "renamed".into();
+        assert_eq!(
+            loader.receive(file, level.to_bytes(), &mut assets),
+            Ok(true)
+        );
+        assert_eq!(assets.level(1).name, "renamed");
+
+        assert!(loader.receive(file, b"FQBL".to_vec(), &mut assets).is_err());
+        assert_eq!(assets.level(1).name, "renamed");
+    }
+}
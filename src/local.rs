@@ -1,30 +1,45 @@
 // copyright 2022 Remi Bernotavicius
 
-use super::{game, graphics, input, AppState};
+use super::{game, graphics, input, rules, AppState};
 use bevy::prelude::*;
 use input::InputStream;
 use std::iter;
 
+#[allow(clippy::too_many_arguments)]
 fn move_sprites(
     mut input_stream: NonSendMut<InputStream>,
-    frame_counter: Res<game::FrameCounter>,
-    mut object_query: Query<(
-        &mut graphics::Bounds,
-        &mut game::Velocity,
-        &mut game::Player,
-    )>,
+    mut frame_counter: ResMut<game::FrameCounter>,
+    mut clock: ResMut<rules::MatchClock>,
+    mut end: ResMut<rules::MatchEnd>,
+    rules: Res<rules::MatchRules>,
+    mut object_query: game::PlayerQuery,
+    platforms: game::PlatformQuery,
+    hazards: game::HazardQuery,
 ) {
     let input = iter::from_fn(|| input_stream.get()).collect();
 
-    for (_, mut velocity, mut player) in object_query.iter_mut() {
+    for (_, mut velocity, mut player, ..) in object_query.iter_mut() {
         game::move_player(&frame_counter, input, &mut player, &mut velocity);
     }
 
-    game::physics(&frame_counter, object_query);
+    game::step(
+        &mut frame_counter,
+        &mut clock,
+        &mut end,
+        &rules,
+        &mut object_query,
+        &platforms,
+        &hazards,
+    );
 }
 
-fn spawn_player(mut commands: Commands) {
-    game::Player::spawn(&mut commands, 0);
+fn spawn_player(
+    mut commands: Commands,
+    rules: Res<rules::MatchRules>,
+    assets: Res<graphics::Assets>,
+) {
+    let level = assets.level(rules.level());
+    game::Player::spawn(&mut commands, 0, &rules, level);
 }
 
 pub struct Plugin;
@@ -32,7 +47,12 @@ pub struct Plugin;
 impl bevy::app::Plugin for Plugin {
     fn build(&self, app: &mut App) {
         app.add_system_set(
-            SystemSet::on_update(AppState::SinglePlayerGame).with_system(move_sprites),
+            SystemSet::on_update(AppState::SinglePlayerGame)
+                .with_system(move_sprites.label("step").before("draw_background")),
+        )
+        .add_system_set(
+            SystemSet::on_update(AppState::SinglePlayerGame)
+                .with_system(game::animate_players.after("step").label("animate")),
         )
         .add_system_set(SystemSet::on_enter(AppState::SinglePlayerGame).with_system(spawn_player));
     }
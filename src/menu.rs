@@ -1,11 +1,12 @@
 // copyright 2022 Remi Bernotavicius
 
-use super::{despawn_screen, graphics, input, renderer, AppState};
+use super::{despawn_screen, graphics, input, renderer, rules, AppState};
 use bevy::prelude::*;
 use euclid::{Point2D, Rect, Size2D};
-use graphics::{Bounds, SimpleSprite, TextBox, PALLET};
+use graphics::{Assets, Bounds, ScreenPallet, SimpleSprite, TextBox};
 use input::{Input, InputStream};
-use renderer::Pixels;
+use renderer::{PalletColor, Pixels};
+use rules::MatchRules;
 use std::iter;
 
 #[derive(Component)]
@@ -17,16 +18,37 @@ struct MenuMarker;
 #[derive(Component)]
 struct MenuText;
 
+/// What happens when a menu entry is chosen
+#[derive(Clone, Copy)]
+pub(crate) enum MenuAction {
+    Start(AppState),
+    NextPallet,
+    NextWinCondition,
+    NextLevel,
+}
+
+fn pallet_text(pallet: &ScreenPallet) -> String {
+    format!("colors: {}", pallet.preset().name())
+}
+
+fn win_condition_text(rules: &MatchRules) -> String {
+    format!("match: {}", rules.win().name())
+}
+
+fn level_text(rules: &MatchRules, assets: &Assets) -> String {
+    format!("level: {}", assets.level(rules.level()).name)
+}
+
 #[derive(Component)]
-struct Menu {
+pub(crate) struct Menu {
     pos: usize,
-    entries: Vec<(Entity, AppState)>,
+    entries: Vec<(Entity, MenuAction)>,
     marker: Entity,
 }
 
 impl Menu {
-    fn new(entries: Vec<(Entity, AppState)>, marker: Entity) -> Self {
-        assert!(entries.len() > 0);
+    fn new(entries: Vec<(Entity, MenuAction)>, marker: Entity) -> Self {
+        assert!(!entries.is_empty());
         Self {
             pos: 0,
             entries,
@@ -38,46 +60,46 @@ impl Menu {
         textboxes.get_mut(self.entries[self.pos].0).unwrap()
     }
 
-    fn current_app_state<'a>(&self) -> AppState {
+    fn current_action(&self) -> MenuAction {
         self.entries[self.pos].1
     }
 
     fn up(&mut self, marker_bounds: &mut Bounds, textboxes: &mut Query<&mut TextBox>) {
         if self.pos > 0 {
-            self.current_text(textboxes).color = PALLET[1];
+            self.current_text(textboxes).color = PalletColor::Color2;
             self.pos -= 1;
-            self.current_text(textboxes).color = PALLET[3];
+            self.current_text(textboxes).color = PalletColor::Color4;
             marker_bounds.0.origin.y -= 10;
         }
     }
 
     fn down(&mut self, marker_bounds: &mut Bounds, textboxes: &mut Query<&mut TextBox>) {
         if self.pos < self.entries.len() - 1 {
-            self.current_text(textboxes).color = PALLET[1];
+            self.current_text(textboxes).color = PalletColor::Color2;
             self.pos += 1;
-            self.current_text(textboxes).color = PALLET[3];
+            self.current_text(textboxes).color = PalletColor::Color4;
             marker_bounds.0.origin.y += 10;
         }
     }
 
-    fn spawn(
+    pub(crate) fn spawn(
         pos: impl Into<Point2D<i32, Pixels>>,
-        items: &[(&str, AppState)],
+        items: &[(&str, MenuAction)],
         mut commands: Commands,
     ) {
         let menu_pos = pos.into();
-        let mut text_pos = menu_pos.clone();
+        let mut text_pos = menu_pos;
         text_pos.x += 5;
 
         let mut entries = vec![];
-        let colors = iter::once(PALLET[3]).chain(iter::repeat(PALLET[1]));
-        for (&(text, state), color) in items.into_iter().zip(colors) {
+        let colors = iter::once(PalletColor::Color4).chain(iter::repeat(PalletColor::Color2));
+        for (&(text, action), color) in items.iter().zip(colors) {
             entries.push((
                 TextBox::spawn(&mut commands, text, text_pos, color)
                     .insert(OnMenu)
                     .insert(MenuText)
                     .id(),
-                state,
+                action,
             ));
             text_pos.y += 10;
         }
@@ -86,7 +108,8 @@ impl Menu {
             .spawn()
             .insert(SimpleSprite {
                 tile: '>'.into(),
-                color: PALLET[3],
+                colors: PalletColor::Color4.into(),
+                orientation: Default::default(),
             })
             .insert(Bounds(Rect::new(menu_pos, Size2D::new(10, 10))))
             .insert(MenuMarker)
@@ -99,38 +122,60 @@ impl Menu {
             .insert(OnMenu);
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn update(
         mut self_query: Query<&mut Self>,
         mut marker_query: Query<&mut Bounds, With<MenuMarker>>,
         mut textboxes: Query<&mut TextBox>,
         mut input_stream: NonSendMut<InputStream>,
         mut app_state: ResMut<State<AppState>>,
+        mut pallet: ResMut<ScreenPallet>,
+        mut rules: ResMut<MatchRules>,
+        assets: Res<Assets>,
     ) {
         let mut self_ = self_query.iter_mut().next().unwrap();
         let mut marker_bounds = marker_query.get_mut(self_.marker).unwrap();
 
         while let Some(i) = input_stream.get() {
             match i {
-                Input::Primary => {
-                    app_state.set(self_.current_app_state()).unwrap();
-                }
-                Input::Up => self_.up(&mut *marker_bounds, &mut textboxes),
-                Input::Down => self_.down(&mut *marker_bounds, &mut textboxes),
+                Input::Primary => match self_.current_action() {
+                    MenuAction::Start(state) => app_state.set(state).unwrap(),
+                    MenuAction::NextPallet => {
+                        let next = pallet.preset().next();
+                        pallet.set_preset(next);
+                        self_.current_text(&mut textboxes).text = pallet_text(&pallet);
+                    }
+                    MenuAction::NextWinCondition => {
+                        let next = rules.win().next();
+                        rules.set_win(next);
+                        self_.current_text(&mut textboxes).text = win_condition_text(&rules);
+                    }
+                    MenuAction::NextLevel => {
+                        rules.next_level();
+                        self_.current_text(&mut textboxes).text = level_text(&rules, &assets);
+                    }
+                },
+                Input::Up => self_.up(&mut marker_bounds, &mut textboxes),
+                Input::Down => self_.down(&mut marker_bounds, &mut textboxes),
                 _ => {}
             }
         }
     }
 }
 
+/// Lets a `Menu` spawned when entering `state` be used, and despawns it when leaving
+pub(crate) fn add_menu_systems(app: &mut App, state: AppState) {
+    app.add_system_set(SystemSet::on_update(state).with_system(Menu::update))
+        .add_system_set(SystemSet::on_exit(state).with_system(despawn_screen::<OnMenu>));
+}
+
 pub struct Plugin;
 
 impl bevy::app::Plugin for Plugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set(SystemSet::on_enter(AppState::Menu).with_system(spawn_sprites))
-            .add_system_set(SystemSet::on_update(AppState::Menu).with_system(Menu::update))
-            .add_system_set(
-                SystemSet::on_exit(AppState::Menu).with_system(despawn_screen::<OnMenu>),
-            );
+        app.init_resource::<MatchRules>()
+            .add_system_set(SystemSet::on_enter(AppState::Menu).with_system(spawn_sprites));
+        add_menu_systems(app, AppState::Menu);
     }
 
     fn name(&self) -> &str {
@@ -138,13 +183,32 @@ impl bevy::app::Plugin for Plugin {
     }
 }
 
-fn spawn_sprites(mut commands: Commands) {
-    TextBox::spawn(&mut commands, "frog quest battle", (10, 40), PALLET[2]).insert(OnMenu);
+fn spawn_sprites(
+    mut commands: Commands,
+    pallet: Res<ScreenPallet>,
+    rules: Res<MatchRules>,
+    assets: Res<Assets>,
+) {
+    TextBox::spawn(
+        &mut commands,
+        "frog quest battle",
+        (10, 40),
+        PalletColor::Color3,
+    )
+    .insert(OnMenu);
     Menu::spawn(
         (10, 60),
         &[
-            ("single player", AppState::SinglePlayerGame),
-            ("multiplayer", AppState::MultiplayerGame),
+            (
+                "single player",
+                MenuAction::Start(AppState::SinglePlayerGame),
+            ),
+            #[cfg(target_arch = "wasm32")]
+            ("multiplayer", MenuAction::Start(AppState::MultiplayerGame)),
+            (&win_condition_text(&rules), MenuAction::NextWinCondition),
+            (&level_text(&rules, &assets), MenuAction::NextLevel),
+            (&pallet_text(&pallet), MenuAction::NextPallet),
+            ("sprite editor", MenuAction::Start(AppState::SpriteEditor)),
         ],
         commands,
     );
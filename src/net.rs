@@ -1,6 +1,6 @@
 // copyright 2022 Remi Bernotavicius
 
-use super::{game, graphics, input, AppState};
+use super::{game, graphics, input, rules, AppState};
 use bevy::prelude::*;
 use bevy::tasks::IoTaskPool;
 use bevy_ggrs::*;
@@ -22,33 +22,48 @@ fn input(_: In<ggrs::PlayerHandle>, mut input_stream: NonSendMut<InputStream>) -
     vec![set.as_u8()]
 }
 
+#[allow(clippy::too_many_arguments)]
 fn move_sprites(
     inputs: Res<Vec<ggrs::GameInput>>,
-    frame_counter: Res<game::FrameCounter>,
-    mut object_query: Query<(
-        &mut graphics::Bounds,
-        &mut game::Velocity,
-        &mut game::Player,
-    )>,
+    mut frame_counter: ResMut<game::FrameCounter>,
+    mut clock: ResMut<rules::MatchClock>,
+    mut end: ResMut<rules::MatchEnd>,
+    rules: Res<rules::MatchRules>,
+    mut object_query: game::PlayerQuery,
+    platforms: game::PlatformQuery,
+    hazards: game::HazardQuery,
 ) {
-    for (_, mut velocity, mut player) in object_query.iter_mut() {
+    for (_, mut velocity, mut player, ..) in object_query.iter_mut() {
         let input = EnumSet::from_u8(inputs[player.handle as usize].buffer[0]);
         game::move_player(&frame_counter, input, &mut player, &mut velocity);
     }
 
-    game::physics(&frame_counter, object_query);
+    game::step(
+        &mut frame_counter,
+        &mut clock,
+        &mut end,
+        &rules,
+        &mut object_query,
+        &platforms,
+        &hazards,
+    );
 }
 
 fn start_matchbox_socket(
     mut commands: Commands,
     mut game_status: ResMut<game::GameStatus>,
     task_pool: Res<IoTaskPool>,
+    rules: Res<rules::MatchRules>,
+    assets: Res<graphics::Assets>,
 ) {
     game_status.set_message("connecting");
 
-    let room_url = "ws://remi.party:3536/next_2";
+    // Peers are only matched with others playing by the same rules on the same level, so a level
+    // reloaded on one peer doesn't end up played against the old one
+    let level = assets.level(rules.level());
+    let room_url = format!("ws://remi.party:3536/next_2_{}", rules.session_key(level));
     log::info!("connecting to matchbox server: {:?}", room_url);
-    let (socket, message_loop) = WebRtcNonBlockingSocket::new(room_url);
+    let (socket, message_loop) = WebRtcNonBlockingSocket::new(&room_url);
 
     // The message loop needs to be awaited, or nothing will happen.
     // We do this here using bevy's task system.
@@ -109,9 +124,23 @@ fn wait_for_players(
     commands.start_p2p_session(p2p_session);
 }
 
-fn spawn_players(mut commands: Commands, mut rip: ResMut<RollbackIdProvider>) {
+/// Ends the session once the results of the match have been seen. It keeps running on the results
+/// screen so the other peer still gets the inputs it needs to confirm the end of the match, then a
+/// rematch starts over with a new session.
+fn stop_session(mut commands: Commands) {
+    commands.stop_session();
+}
+
+fn spawn_players(
+    mut commands: Commands,
+    mut rip: ResMut<RollbackIdProvider>,
+    rules: Res<rules::MatchRules>,
+    assets: Res<graphics::Assets>,
+) {
+    let level = assets.level(rules.level());
     for handle in 0..2 {
-        game::Player::spawn(&mut commands, handle).insert(Rollback::new(rip.next_id()));
+        game::Player::spawn(&mut commands, handle, &rules, level)
+            .insert(Rollback::new(rip.next_id()));
     }
 }
 
@@ -120,10 +149,14 @@ pub struct Plugin;
 impl bevy::app::Plugin for Plugin {
     fn build(&self, app: &mut App) {
         app.add_plugin(GGRSPlugin)
-            .with_rollback_schedule(Schedule::default().with_stage(
-                "ROLLBACK_STAGE",
-                SystemStage::single_threaded().with_system(move_sprites),
-            ))
+            .with_rollback_schedule(
+                Schedule::default().with_stage(
+                    "ROLLBACK_STAGE",
+                    SystemStage::single_threaded()
+                        .with_system(move_sprites.label("step"))
+                        .with_system(game::animate_players.after("step")),
+                ),
+            )
             .with_input_system(input)
             .add_system_set(
                 SystemSet::on_enter(AppState::MultiplayerGame).with_system(spawn_players),
@@ -133,6 +166,7 @@ impl bevy::app::Plugin for Plugin {
             )
             .add_system_set(
                 SystemSet::on_update(AppState::MultiplayerGame).with_system(wait_for_players),
-            );
+            )
+            .add_system_set(SystemSet::on_exit(AppState::Results).with_system(stop_session));
     }
 }
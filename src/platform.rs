@@ -0,0 +1,287 @@
+// copyright 2022 Remi Bernotavicius
+
+//! Platforms players can stand on. Solid platforms block players from every side, while one-way
+//! platforms can be jumped up through and only stop players falling on to them from above.
+//!
+//! Players move one axis at a time, horizontally then vertically, and stop at the nearest platform
+//! in their way. Only the nearest one matters, so the order platforms are checked in doesn't change
+//! the outcome.
+
+use super::graphics::{Assets, Bounds, PointIterExt as _, Sprite};
+use super::renderer::{PalletColor, Pixels, Renderer, RENDER_RECT};
+use bevy::ecs::system::EntityCommands;
+use bevy::prelude::*;
+use euclid::{Rect, Vector2D};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlatformKind {
+    /// Floats on the water and can be jumped up through
+    LilyPad,
+    Log,
+    Rock,
+}
+
+impl PlatformKind {
+    pub const ALL: [Self; 3] = [Self::LilyPad, Self::Log, Self::Rock];
+
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::LilyPad => "lily_pad",
+            Self::Log => "log",
+            Self::Rock => "rock",
+        }
+    }
+
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.name() == name)
+    }
+
+    /// Whether players can pass through the platform from below and from the sides
+    pub fn one_way(self) -> bool {
+        self == Self::LilyPad
+    }
+
+    /// The color of the top edge, and of the rest of the platform
+    fn colors(self) -> (PalletColor, PalletColor) {
+        match self {
+            Self::LilyPad => (PalletColor::Color4, PalletColor::Color2),
+            Self::Log => (PalletColor::Color4, PalletColor::Color3),
+            Self::Rock => (PalletColor::Color3, PalletColor::Color4),
+        }
+    }
+}
+
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Platform {
+    pub kind: PlatformKind,
+}
+
+impl Platform {
+    pub fn spawn<'a, 'w, 's>(
+        commands: &'a mut Commands<'w, 's>,
+        kind: PlatformKind,
+        rect: Rect<i32, Pixels>,
+    ) -> EntityCommands<'w, 's, 'a> {
+        let mut entity = commands.spawn();
+        entity.insert(Self { kind }).insert(Bounds(rect));
+        entity
+    }
+}
+
+impl Sprite for Platform {
+    fn draw(
+        &self,
+        bounds: &Bounds,
+        clip: Rect<i32, Pixels>,
+        _assets: &Assets,
+        renderer: &mut impl Renderer,
+    ) {
+        let (top, body) = self.kind.colors();
+        if let Some(visible) = bounds.0.intersection(&clip) {
+            for p in visible.point_iter() {
+                let color = if p.y == bounds.0.min_y() { top } else { body };
+                renderer.color_pixel(p, color);
+            }
+        }
+    }
+}
+
+/// Every place each platform can be collided with. Players wrap around the left and right edges
+/// of the screen, so they can touch a platform on the other side.
+fn wrapped(
+    platforms: &[(Rect<i32, Pixels>, PlatformKind)],
+) -> impl Iterator<Item = (Rect<i32, Pixels>, PlatformKind)> + '_ {
+    let width = RENDER_RECT.width();
+    platforms.iter().flat_map(move |&(rect, kind)| {
+        [0, -width, width].map(|offset| (rect.translate(Vector2D::new(offset, 0)), kind))
+    })
+}
+
+fn overlaps_horizontally(a: &Rect<i32, Pixels>, b: &Rect<i32, Pixels>) -> bool {
+    a.min_x() < b.max_x() && a.max_x() > b.min_x()
+}
+
+fn overlaps_vertically(a: &Rect<i32, Pixels>, b: &Rect<i32, Pixels>) -> bool {
+    a.min_y() < b.max_y() && a.max_y() > b.min_y()
+}
+
+/// How far along the x axis `rect` can move towards `dx` before hitting a solid platform, if
+/// one is in the way
+fn horizontal_stop(
+    rect: &Rect<i32, Pixels>,
+    dx: i32,
+    platforms: &[(Rect<i32, Pixels>, PlatformKind)],
+) -> Option<i32> {
+    let solid =
+        wrapped(platforms).filter(|(p, kind)| !kind.one_way() && overlaps_vertically(rect, p));
+    if dx > 0 {
+        solid
+            .filter(|(p, _)| rect.max_x() <= p.min_x() && rect.max_x() + dx > p.min_x())
+            .map(|(p, _)| p.min_x() - rect.max_x())
+            .min()
+    } else if dx < 0 {
+        solid
+            .filter(|(p, _)| rect.min_x() >= p.max_x() && rect.min_x() + dx < p.max_x())
+            .map(|(p, _)| p.max_x() - rect.min_x())
+            .max()
+    } else {
+        None
+    }
+}
+
+/// How far along the y axis `rect` can move towards `dy` before landing on a platform or hitting
+/// the bottom of a solid one, if one is in the way
+fn vertical_stop(
+    rect: &Rect<i32, Pixels>,
+    dy: i32,
+    platforms: &[(Rect<i32, Pixels>, PlatformKind)],
+) -> Option<i32> {
+    let in_line = wrapped(platforms).filter(|(p, _)| overlaps_horizontally(rect, p));
+    if dy > 0 {
+        in_line
+            .filter(|(p, _)| rect.max_y() <= p.min_y() && rect.max_y() + dy > p.min_y())
+            .map(|(p, _)| p.min_y() - rect.max_y())
+            .min()
+    } else if dy < 0 {
+        in_line
+            .filter(|(_, kind)| !kind.one_way())
+            .filter(|(p, _)| rect.min_y() >= p.max_y() && rect.min_y() + dy < p.max_y())
+            .map(|(p, _)| p.max_y() - rect.min_y())
+            .max()
+    } else {
+        None
+    }
+}
+
+/// Moves `rect` by `velocity`, stopping it at platforms. Running in to the side of a platform stops
+/// horizontal movement, landing on one stops falling, and hitting the bottom of one bounces you
+/// back down like the ceiling does.
+pub fn move_and_collide(
+    rect: &mut Rect<i32, Pixels>,
+    velocity: &mut Vector2D<i32, Pixels>,
+    platforms: &[(Rect<i32, Pixels>, PlatformKind)],
+) {
+    match horizontal_stop(rect, velocity.x, platforms) {
+        Some(dx) => {
+            rect.origin.x += dx;
+            velocity.x = 0;
+        }
+        None => rect.origin.x += velocity.x,
+    }
+
+    match vertical_stop(rect, velocity.y, platforms) {
+        Some(dy) if velocity.y > 0 => {
+            rect.origin.y += dy;
+            velocity.y = 0;
+        }
+        Some(dy) => {
+            rect.origin.y += dy;
+            velocity.y *= -1;
+            velocity.y /= 2;
+        }
+        None => rect.origin.y += velocity.y,
+    }
+}
+
+/// Whether `rect` is resting on top of a platform
+pub fn standing_on_platform(
+    rect: &Rect<i32, Pixels>,
+    platforms: &[(Rect<i32, Pixels>, PlatformKind)],
+) -> bool {
+    wrapped(platforms).any(|(p, _)| p.min_y() == rect.max_y() && overlaps_horizontally(rect, &p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player_at(x: i32, y: i32) -> Rect<i32, Pixels> {
+        Rect::new((x, y).into(), (10, 10).into())
+    }
+
+    fn platforms() -> Vec<(Rect<i32, Pixels>, PlatformKind)> {
+        vec![
+            (
+                Rect::new((50, 100).into(), (40, 8).into()),
+                PlatformKind::Log,
+            ),
+            (
+                Rect::new((50, 60).into(), (40, 4).into()),
+                PlatformKind::LilyPad,
+            ),
+        ]
+    }
+
+    #[test]
+    fn players_land_on_platforms() {
+        let platforms = platforms();
+        let mut rect = player_at(60, 85);
+        let mut velocity = Vector2D::new(1, 8);
+        move_and_collide(&mut rect, &mut velocity, &platforms);
+        assert_eq!(rect.origin, (61, 90).into());
+        assert_eq!(velocity, Vector2D::new(1, 0));
+        assert!(standing_on_platform(&rect, &platforms));
+
+        // One-way platforms hold players up too
+        let mut rect = player_at(60, 48);
+        let mut velocity = Vector2D::new(0, 3);
+        move_and_collide(&mut rect, &mut velocity, &platforms);
+        assert_eq!(rect.origin, (60, 50).into());
+        assert!(standing_on_platform(&rect, &platforms));
+
+        // Walking off the edge leaves nothing underneath
+        assert!(!standing_on_platform(&player_at(90, 90), &platforms));
+    }
+
+    #[test]
+    fn solid_platforms_block_from_below_and_the_sides() {
+        let platforms = platforms();
+        let mut rect = player_at(60, 110);
+        let mut velocity = Vector2D::new(0, -4);
+        move_and_collide(&mut rect, &mut velocity, &platforms);
+        assert_eq!(rect.origin, (60, 108).into());
+        assert_eq!(velocity, Vector2D::new(0, 2));
+
+        let mut rect = player_at(38, 98);
+        let mut velocity = Vector2D::new(4, 0);
+        move_and_collide(&mut rect, &mut velocity, &platforms);
+        assert_eq!(rect.origin, (40, 98).into());
+        assert_eq!(velocity, Vector2D::zero());
+
+        let mut rect = player_at(91, 98);
+        let mut velocity = Vector2D::new(-2, 0);
+        move_and_collide(&mut rect, &mut velocity, &platforms);
+        assert_eq!(rect.origin, (90, 98).into());
+    }
+
+    #[test]
+    fn players_jump_up_through_one_way_platforms() {
+        let platforms = platforms();
+        let mut rect = player_at(60, 66);
+        let mut velocity = Vector2D::new(2, -4);
+        move_and_collide(&mut rect, &mut velocity, &platforms);
+        assert_eq!(rect.origin, (62, 62).into());
+        assert_eq!(velocity, Vector2D::new(2, -4));
+
+        let mut rect = player_at(38, 58);
+        let mut velocity = Vector2D::new(4, 0);
+        move_and_collide(&mut rect, &mut velocity, &platforms);
+        assert_eq!(rect.origin, (42, 58).into());
+    }
+
+    #[test]
+    fn platforms_collide_across_the_screen_edge() {
+        let platforms = vec![(
+            Rect::new((0, 100).into(), (20, 8).into()),
+            PlatformKind::Rock,
+        )];
+        let mut rect = player_at(RENDER_RECT.width() - 5, 88);
+        let mut velocity = Vector2D::new(0, 4);
+        move_and_collide(&mut rect, &mut velocity, &platforms);
+        assert_eq!(rect.origin.y, 90);
+        assert!(standing_on_platform(&rect, &platforms));
+    }
+}
@@ -1,259 +1,241 @@
 // copyright 2022 Remi Bernotavicius
 
-use euclid::{Length, Point2D, Rect, Scale, Size2D};
+use euclid::{Box2D, Length, Point2D, Rect, Scale, Size2D};
 use serde::{Deserialize, Serialize};
-use wasm_bindgen::JsCast;
-use web_sys::{WebGl2RenderingContext, WebGlProgram, WebGlShader, WebGlTexture};
+use std::mem;
 
 /// Unit for pixels of the renderer
 pub struct Pixels;
 
-/// Unit for pixels in WebGl
-pub struct WebGlPixels;
-
 /// Unit for a number of bytes
 struct Bytes;
 
-pub fn compile_shader(
-    context: &WebGl2RenderingContext,
-    shader_type: u32,
-    source: &str,
-) -> Result<WebGlShader, String> {
-    let shader = context
-        .create_shader(shader_type)
-        .ok_or_else(|| String::from("Unable to create shader object"))?;
-    context.shader_source(&shader, source);
-    context.compile_shader(&shader);
-
-    if context
-        .get_shader_parameter(&shader, WebGl2RenderingContext::COMPILE_STATUS)
-        .as_bool()
-        .unwrap_or(false)
-    {
-        Ok(shader)
-    } else {
-        Err(context
-            .get_shader_info_log(&shader)
-            .unwrap_or_else(|| String::from("Unknown error creating shader")))
-    }
+pub const RENDER_RECT: Rect<i32, Pixels> = Rect {
+    origin: Point2D::<i32, Pixels>::new(0, 0),
+    size: Size2D::<i32, Pixels>::new(384, 216),
+};
+
+/// Something the game can draw pixels on to and then display.
+pub trait Renderer {
+    fn color_pixel(&mut self, pos: Point2D<i32, Pixels>, color: PalletColor);
+
+    /// Colors every pixel at once, from a `PalletColor` for each pixel of `RENDER_RECT` row by row.
+    /// This is much faster than coloring them one at a time.
+    fn color_screen(&mut self, pixels: &[PalletColor]);
+
+    /// Sets the colors `PalletColor`s are displayed as. Takes effect at the next `present`, even
+    /// for pixels already drawn.
+    fn set_pallet(&mut self, pallet: Pallet);
+
+    /// Make everything drawn since the last call visible to `render`
+    fn present(&mut self);
+
+    fn render(&self);
+
+    /// The pixels drawn so far
+    fn framebuffer(&self) -> &Framebuffer;
 }
 
-pub fn link_program(
-    context: &WebGl2RenderingContext,
-    vert_shader: &WebGlShader,
-    frag_shader: &WebGlShader,
-) -> Result<WebGlProgram, String> {
-    let program = context
-        .create_program()
-        .ok_or_else(|| String::from("Unable to create shader object"))?;
-
-    context.attach_shader(&program, vert_shader);
-    context.attach_shader(&program, frag_shader);
-    context.link_program(&program);
-
-    if context
-        .get_program_parameter(&program, WebGl2RenderingContext::LINK_STATUS)
-        .as_bool()
-        .unwrap_or(false)
-    {
-        Ok(program)
-    } else {
-        Err(context
-            .get_program_info_log(&program)
-            .unwrap_or_else(|| String::from("Unknown error creating program object")))
+/// How many pixels handling a rect separately is worth. Two rects are merged in to one when that
+/// covers fewer unchanged pixels in between them than this.
+const RECT_COST: i32 = 256;
+
+/// The most rects `DirtyRects` keeps apart before merging them regardless
+const MAX_DIRTY_RECTS: usize = 8;
+
+/// The parts of the screen which changed, kept as a few separate rects so that changes far apart
+/// don't cover everything in between them
+#[derive(Default)]
+struct DirtyRects(Vec<Box2D<i32, Pixels>>);
+
+impl DirtyRects {
+    fn add(&mut self, area: Box2D<i32, Pixels>) {
+        if self.0.iter().any(|r| r.contains_box(&area)) {
+            return;
+        }
+        self.0.retain(|r| !area.contains_box(r));
+
+        // How many unchanged pixels merging with `r` would cover
+        let extra = |r: &Box2D<i32, Pixels>| r.union(&area).area() - r.area() - area.area();
+        let cheapest = self.0.iter().enumerate().min_by_key(|(_, r)| extra(r));
+        match cheapest {
+            Some((i, r)) if extra(r) <= RECT_COST || self.0.len() == MAX_DIRTY_RECTS => {
+                self.0[i] = r.union(&area);
+            }
+            _ => self.0.push(area),
+        }
+    }
+
+    fn take(&mut self) -> Vec<Box2D<i32, Pixels>> {
+        mem::take(&mut self.0)
     }
 }
 
-pub struct CanvasRenderer {
-    context: WebGl2RenderingContext,
-    texture: WebGlTexture,
+/// An in-memory buffer the size of `RENDER_RECT`. Pixels are drawn as `PalletColor`s, and
+/// converted to RGBA using the current `Pallet` by `present`.
+pub struct Framebuffer {
+    indices: Vec<PalletColor>,
+    pallet: Pallet,
     buffer: Vec<u8>,
+    /// Pixels drawn since the last `present`
+    changed: DirtyRects,
+    /// Pixels presented since the last `take_dirty_rects`
+    dirty: DirtyRects,
 }
 
-pub const RENDER_RECT: Rect<i32, Pixels> = Rect {
-    origin: Point2D::<i32, Pixels>::new(0, 0),
-    size: Size2D::<i32, Pixels>::new(384, 216),
-};
+impl Framebuffer {
+    pub fn new() -> Self {
+        Self {
+            indices: vec![PalletColor::Color1; RENDER_RECT.area() as usize],
+            pallet: Pallet([Color::BLACK; 4]),
+            buffer: vec![
+                u8::MAX;
+                (Length::new(RENDER_RECT.area() as usize) * BYTES_PER_PIXEL).get()
+            ],
+            changed: DirtyRects(vec![RENDER_RECT.to_box2d()]),
+            dirty: DirtyRects::default(),
+        }
+    }
 
-pub const PIXEL_SCALE: Scale<i32, Pixels, WebGlPixels> = Scale::new(4);
+    /// The RGBA pixels as of the last `present`
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer[..]
+    }
 
-fn get_rendering_context(canvas: &web_sys::HtmlCanvasElement) -> WebGl2RenderingContext {
-    canvas
-        .get_context("webgl2")
-        .unwrap()
-        .unwrap()
-        .dyn_into::<WebGl2RenderingContext>()
-        .unwrap()
-}
+    /// The `PalletColor` of every pixel, row by row
+    pub fn pallet_indices(&self) -> &[PalletColor] {
+        &self.indices[..]
+    }
 
-fn set_rectangle(context: &WebGl2RenderingContext, x: f32, y: f32, width: f32, height: f32) {
-    let x1 = x;
-    let x2 = x + width;
-    let y1 = y;
-    let y2 = y + height;
-    let data: [f32; 12] = [x1, y1, x2, y1, x1, y2, x1, y2, x2, y1, x2, y2];
-    unsafe {
-        let data_array = js_sys::Float32Array::view(&data);
-        context.buffer_data_with_array_buffer_view(
-            WebGl2RenderingContext::ARRAY_BUFFER,
-            &data_array,
-            WebGl2RenderingContext::STATIC_DRAW,
+    pub fn pallet(&self) -> Pallet {
+        self.pallet
+    }
+
+    /// Returns areas containing every pixel changed by `present` since the last call, if any.
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    pub fn take_dirty_rects(&mut self) -> Vec<Rect<i32, Pixels>> {
+        self.dirty.take().iter().map(|d| d.to_rect()).collect()
+    }
+
+    /// Everything drawn so far, whether or not it has been presented
+    pub fn to_bmp(&self) -> bmp::Image {
+        let mut image = bmp::Image::new(
+            RENDER_RECT.size.width as u32,
+            RENDER_RECT.size.height as u32,
         );
+        for (x, y) in image.coordinates() {
+            let color = self
+                .pallet
+                .color(self.indices[(y * image.get_width() + x) as usize]);
+            image.set_pixel(x, y, bmp::Pixel::new(color.r, color.g, color.b));
+        }
+        image
+    }
+
+    fn mark_changed(&mut self, area: Box2D<i32, Pixels>) {
+        self.changed.add(area);
     }
 }
 
-fn set_up_context(context: &WebGl2RenderingContext, texture: &WebGlTexture) {
-    let vert_shader = compile_shader(
-        context,
-        WebGl2RenderingContext::VERTEX_SHADER,
-        r#"# version 300 es
-        // an attribute is an input (in) to a vertex shader.
-        // It will receive data from a buffer
-        in vec2 a_position;
-        in vec2 a_texCoord;
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        // Used to pass in the resolution of the canvas
-        uniform vec2 u_resolution;
+impl Renderer for Framebuffer {
+    #[inline(always)]
+    fn color_pixel(&mut self, pos: Point2D<i32, Pixels>, color: PalletColor) {
+        assert!(RENDER_RECT.contains(pos), "{pos:?} not in {RENDER_RECT:?}");
+
+        let i = (pos.y * RENDER_RECT.size.width + pos.x) as usize;
+        if self.indices[i] != color {
+            self.indices[i] = color;
+            self.mark_changed(Box2D::new(pos, pos + Size2D::new(1, 1)));
+        }
+    }
+
+    fn color_screen(&mut self, pixels: &[PalletColor]) {
+        assert_eq!(pixels.len(), self.indices.len());
+
+        // Only rows which differ count as changed
+        let width = RENDER_RECT.size.width as usize;
+        for (y, (row, new_row)) in self
+            .indices
+            .chunks_mut(width)
+            .zip(pixels.chunks(width))
+            .enumerate()
+        {
+            if row != new_row {
+                row.copy_from_slice(new_row);
+                let y = y as i32;
+                self.changed.add(Box2D::new(
+                    Point2D::new(0, y),
+                    Point2D::new(RENDER_RECT.size.width, y + 1),
+                ));
+            }
+        }
+    }
+
+    fn set_pallet(&mut self, pallet: Pallet) {
+        if self.pallet != pallet {
+            self.pallet = pallet;
+            self.mark_changed(RENDER_RECT.to_box2d());
+        }
+    }
 
-        // Used to pass the texture coordinates to the fragment shader
-        out vec2 v_texCoord;
+    fn present(&mut self) {
+        for changed in self.changed.take() {
+            for y in changed.min.y..changed.max.y {
+                for x in changed.min.x..changed.max.x {
+                    let i = (y * RENDER_RECT.size.width + x) as usize;
+                    let color = self.pallet.color(self.indices[i]);
+                    let b = (Length::new(i) * BYTES_PER_PIXEL).get();
+                    self.buffer[b..(b + 4)].copy_from_slice(&[color.r, color.g, color.b, 255]);
+                }
+            }
+            self.dirty.add(changed);
+        }
+    }
 
-        // all shaders have a main function
-        void main() {
+    fn render(&self) {}
 
-          // convert the position from pixels to 0.0 to 1.0
-          vec2 zeroToOne = a_position / u_resolution;
+    fn framebuffer(&self) -> &Framebuffer {
+        self
+    }
+}
 
-          // convert from 0->1 to 0->2
-          vec2 zeroToTwo = zeroToOne * 2.0;
+/// One of the four colors everything is drawn with. Which actual color it is depends on the
+/// `Pallet`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PalletColor {
+    Color1,
+    Color2,
+    Color3,
+    Color4,
+}
 
-          // convert from 0->2 to -1->+1 (clipspace)
-          vec2 clipSpace = zeroToTwo - 1.0;
+impl PalletColor {
+    pub const ALL: [Self; 4] = [Self::Color1, Self::Color2, Self::Color3, Self::Color4];
 
-          gl_Position = vec4(clipSpace * vec2(1, -1), 0, 1);
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
 
-          // pass the texCoord to the fragment shader
-          // The GPU will interpolate this value between points.
-          v_texCoord = a_texCoord;
-        }
-        "#,
-    )
-    .unwrap();
-    let frag_shader = compile_shader(
-        context,
-        WebGl2RenderingContext::FRAGMENT_SHADER,
-        r#"# version 300 es
-        // fragment shaders don't have a default precision so we need
-        // to pick one. highp is a good default. It means "high precision"
-        precision highp float;
-
-        // our texture
-        uniform sampler2D u_image;
-
-        // the texCoords passed in from the vertex shader.
-        in vec2 v_texCoord;
-
-        // we need to declare an output for the fragment shader
-        out vec4 outColor;
-
-        void main() {
-          outColor = texture(u_image, v_texCoord);
-        }
-        "#,
-    )
-    .unwrap();
-
-    let program = link_program(context, &vert_shader, &frag_shader).unwrap();
-
-    let position_attribute_location: u32 = context
-        .get_attrib_location(&program, "a_position")
-        .try_into()
-        .unwrap();
-    let texcoord_attribute_location: u32 = context
-        .get_attrib_location(&program, "a_texCoord")
-        .try_into()
-        .unwrap();
-
-    let resolution_location = context
-        .get_uniform_location(&program, "u_resolution")
-        .unwrap();
-    let image_location = context.get_uniform_location(&program, "u_image").unwrap();
-
-    let vao = context.create_vertex_array().unwrap();
-    context.bind_vertex_array(Some(&vao));
-
-    let position_buffer = context.create_buffer().unwrap();
-    context.enable_vertex_attrib_array(position_attribute_location);
-    context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&position_buffer));
-    context.vertex_attrib_pointer_with_i32(
-        position_attribute_location,
-        2,
-        WebGl2RenderingContext::FLOAT,
-        false,
-        0,
-        0,
-    );
-    let texcoord_buffer = context.create_buffer().unwrap();
-    context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&texcoord_buffer));
-
-    let data: [f32; 12] = [0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0, 1.0];
-    unsafe {
-        let data_array = js_sys::Float32Array::view(&data);
-        context.buffer_data_with_array_buffer_view(
-            WebGl2RenderingContext::ARRAY_BUFFER,
-            &data_array,
-            WebGl2RenderingContext::STATIC_DRAW,
-        );
+/// The actual colors to display each `PalletColor` as
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pallet(pub [Color; 4]);
+
+impl Pallet {
+    pub fn color(&self, c: PalletColor) -> Color {
+        self.0[c.index()]
     }
 
-    context.enable_vertex_attrib_array(texcoord_attribute_location);
-
-    context.vertex_attrib_pointer_with_i32(
-        texcoord_attribute_location,
-        2,
-        WebGl2RenderingContext::FLOAT,
-        false,
-        0,
-        0,
-    );
-
-    context.active_texture(WebGl2RenderingContext::TEXTURE0);
-    context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(texture));
-
-    context.tex_parameteri(
-        WebGl2RenderingContext::TEXTURE_2D,
-        WebGl2RenderingContext::TEXTURE_WRAP_S,
-        WebGl2RenderingContext::CLAMP_TO_EDGE as i32,
-    );
-    context.tex_parameteri(
-        WebGl2RenderingContext::TEXTURE_2D,
-        WebGl2RenderingContext::TEXTURE_WRAP_T,
-        WebGl2RenderingContext::CLAMP_TO_EDGE as i32,
-    );
-    context.tex_parameteri(
-        WebGl2RenderingContext::TEXTURE_2D,
-        WebGl2RenderingContext::TEXTURE_MIN_FILTER,
-        WebGl2RenderingContext::NEAREST as i32,
-    );
-    context.tex_parameteri(
-        WebGl2RenderingContext::TEXTURE_2D,
-        WebGl2RenderingContext::TEXTURE_MAG_FILTER,
-        WebGl2RenderingContext::NEAREST as i32,
-    );
-
-    let screen_rect = RENDER_RECT * PIXEL_SCALE;
-
-    context.viewport(0, 0, screen_rect.size.width, screen_rect.size.height);
-    context.use_program(Some(&program));
-
-    let width = screen_rect.size.width as f32;
-    let height = screen_rect.size.height as f32;
-
-    context.uniform2f(Some(&resolution_location), width, height);
-    context.uniform1i(Some(&image_location), 0);
-
-    context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&position_buffer));
-    set_rectangle(context, 0.0, 0.0, width, height);
+    pub fn map(self, f: impl FnMut(Color) -> Color) -> Self {
+        Self(self.0.map(f))
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -263,64 +245,125 @@ pub struct Color {
     pub b: u8,
 }
 
-/// red, green, blue, and alpha
-const BYTES_PER_PIXEL: Scale<usize, Pixels, Bytes> = Scale::new(4);
-
-impl CanvasRenderer {
-    pub fn new() -> Self {
-        let canvas = super::canvas();
-        let context = get_rendering_context(&canvas);
-        let texture = context.create_texture().unwrap();
-        set_up_context(&context, &texture);
+impl Color {
+    pub const BLACK: Self = Self { r: 0, g: 0, b: 0 };
+    pub const WHITE: Self = Self {
+        r: 255,
+        g: 255,
+        b: 255,
+    };
+
+    /// Returns the color `amount` of the way from `self` to `other`, where `amount` is out of
+    /// `total`. A `total` of 0 is a blend that is already over, giving `other`.
+    pub fn blend(self, other: Self, amount: u32, total: u32) -> Self {
+        assert!(amount <= total);
+        if total == 0 {
+            return other;
+        }
+        let mix = |a: u8, b: u8| ((a as u32 * (total - amount) + b as u32 * amount) / total) as u8;
         Self {
-            context,
-            texture,
-            buffer: vec![
-                u8::MAX;
-                (Length::new(RENDER_RECT.area() as usize) * BYTES_PER_PIXEL).get()
-            ],
+            r: mix(self.r, other.r),
+            g: mix(self.g, other.g),
+            b: mix(self.b, other.b),
         }
     }
+}
 
-    pub fn render(&self) {
-        self.context
-            .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
-    }
+/// red, green, blue, and alpha
+const BYTES_PER_PIXEL: Scale<usize, Pixels, Bytes> = Scale::new(4);
 
-    #[inline(always)]
-    pub fn color_pixel(&mut self, pos: Point2D<i32, Pixels>, color: Color) {
-        assert!(RENDER_RECT.contains(pos), "{pos:?} not in {RENDER_RECT:?}");
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn present_resolves_changed_pixels() {
+        let pallet = Pallet([
+            Color::BLACK,
+            Color { r: 1, g: 2, b: 3 },
+            Color { r: 4, g: 5, b: 6 },
+            Color::WHITE,
+        ]);
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.set_pallet(pallet);
+        framebuffer.present();
+        assert_eq!(framebuffer.take_dirty_rects(), [RENDER_RECT]);
+        assert!(framebuffer.take_dirty_rects().is_empty());
+
+        framebuffer.color_pixel(Point2D::new(3, 4), PalletColor::Color2);
+        framebuffer.color_pixel(Point2D::new(10, 2), PalletColor::Color2);
+        assert!(framebuffer.take_dirty_rects().is_empty());
+        framebuffer.present();
+        assert_eq!(
+            framebuffer.take_dirty_rects(),
+            [Rect::new(Point2D::new(3, 2), Size2D::new(8, 3))]
+        );
+        let i = (4 * RENDER_RECT.size.width + 3) as usize * 4;
+        assert_eq!(&framebuffer.as_bytes()[i..(i + 4)], &[1, 2, 3, 255]);
+
+        // Drawing the same color again doesn't change anything
+        framebuffer.color_pixel(Point2D::new(3, 4), PalletColor::Color2);
+        framebuffer.present();
+        assert!(framebuffer.take_dirty_rects().is_empty());
+
+        // Pixels far apart are kept apart, rather than covering the screen between them
+        framebuffer.color_pixel(Point2D::new(0, 0), PalletColor::Color3);
+        framebuffer.color_pixel(Point2D::new(300, 200), PalletColor::Color3);
+        framebuffer.present();
+        assert_eq!(
+            framebuffer.take_dirty_rects(),
+            [
+                Rect::new(Point2D::new(0, 0), Size2D::new(1, 1)),
+                Rect::new(Point2D::new(300, 200), Size2D::new(1, 1))
+            ]
+        );
 
-        let i = (Length::new((pos.y * RENDER_RECT.size.width + pos.x) as usize) * BYTES_PER_PIXEL)
-            .get();
-        self.buffer[i] = color.r;
-        self.buffer[i + 1] = color.g;
-        self.buffer[i + 2] = color.b;
-        self.buffer[i + 3] = 255;
+        // Changing the pallet changes what is already drawn
+        framebuffer.set_pallet(pallet.map(|_| Color::WHITE));
+        framebuffer.present();
+        assert_eq!(framebuffer.take_dirty_rects(), [RENDER_RECT]);
+        assert_eq!(&framebuffer.as_bytes()[i..(i + 4)], &[255, 255, 255, 255]);
     }
 
-    pub fn present(&mut self) {
-        self.context
-            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&self.texture));
-
-        self.context
-            .tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
-                WebGl2RenderingContext::TEXTURE_2D,
-                0,
-                WebGl2RenderingContext::RGBA as i32,
-                RENDER_RECT.size.width,
-                RENDER_RECT.size.height,
-                0,
-                WebGl2RenderingContext::RGBA,
-                WebGl2RenderingContext::UNSIGNED_BYTE,
-                Some(&self.buffer[..]),
-            )
-            .unwrap();
+    #[test]
+    fn color_screen_marks_changed_rows() {
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.present();
+        framebuffer.take_dirty_rects();
+
+        let mut pixels = framebuffer.pallet_indices().to_vec();
+        framebuffer.color_screen(&pixels);
+        framebuffer.present();
+        assert!(framebuffer.take_dirty_rects().is_empty());
+
+        let width = RENDER_RECT.size.width as usize;
+        pixels[3 * width + 5] = PalletColor::Color3;
+        pixels[6 * width] = PalletColor::Color2;
+        framebuffer.color_screen(&pixels);
+        framebuffer.present();
+        let row = |y| Rect::new(Point2D::new(0, y), Size2D::new(RENDER_RECT.size.width, 1));
+        assert_eq!(framebuffer.take_dirty_rects(), [row(3), row(6)]);
+        assert_eq!(framebuffer.pallet_indices(), &pixels[..]);
     }
-}
 
-impl Default for CanvasRenderer {
-    fn default() -> Self {
-        Self::new()
+    #[test]
+    fn blend_colors() {
+        let c = Color {
+            r: 100,
+            g: 0,
+            b: 255,
+        };
+        assert_eq!(c.blend(Color::BLACK, 0, 4), c);
+        assert_eq!(c.blend(Color::BLACK, 4, 4), Color::BLACK);
+        assert_eq!(
+            c.blend(Color::WHITE, 1, 2),
+            Color {
+                r: 177,
+                g: 127,
+                b: 255
+            }
+        );
+        // Blending over no time at all switches straight away
+        assert_eq!(c.blend(Color::BLACK, 0, 0), Color::BLACK);
     }
 }
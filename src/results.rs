@@ -0,0 +1,90 @@
+// copyright 2022 Remi Bernotavicius
+
+//! The screen shown at the end of a match, with who won and how everyone did. From here the
+//! players can have a rematch or go back to the main menu.
+
+use super::graphics::TextBox;
+use super::menu::{self, Menu, MenuAction};
+use super::renderer::{PalletColor, Pixels, RENDER_RECT};
+use super::rules::MatchResult;
+use super::text::{Align, LINE_HEIGHT};
+use super::{despawn_screen, AppState};
+use bevy::prelude::*;
+use euclid::{Point2D, Rect, Size2D};
+
+#[derive(Component)]
+struct OnResults;
+
+fn title(result: &MatchResult) -> String {
+    match result.winner() {
+        Some(handle) => format!("player {} wins", handle + 1),
+        None => "draw".into(),
+    }
+}
+
+fn standings_text(result: &MatchResult) -> String {
+    let lines: Vec<String> = result
+        .standings
+        .iter()
+        .enumerate()
+        .map(|(place, s)| {
+            format!(
+                "{}. player {}   score {}   lives {}",
+                place + 1,
+                s.handle + 1,
+                s.score,
+                s.lives
+            )
+        })
+        .collect();
+    lines.join("\n")
+}
+
+fn spawn_sprites(mut commands: Commands, result: Res<MatchResult>) {
+    TextBox::spawn_in(
+        &mut commands,
+        title(&result),
+        Rect::new(
+            Point2D::new(10, 40),
+            Size2D::new(RENDER_RECT.width() - 20, 2 * LINE_HEIGHT),
+        ),
+        PalletColor::Color3,
+        Align::Center,
+    )
+    .insert(OnResults);
+
+    let standings_pos: Point2D<i32, Pixels> = Point2D::new(10, 60);
+    TextBox::spawn(
+        &mut commands,
+        standings_text(&result),
+        standings_pos,
+        PalletColor::Color4,
+    )
+    .insert(OnResults);
+
+    let lines = result.standings.len() as i32;
+    Menu::spawn(
+        (10, standings_pos.y + (lines + 1) * LINE_HEIGHT),
+        &[
+            ("rematch", MenuAction::Start(result.game)),
+            ("menu", MenuAction::Start(AppState::Menu)),
+        ],
+        commands,
+    );
+}
+
+pub struct Plugin;
+
+impl bevy::app::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_enter(AppState::Results).with_system(spawn_sprites))
+            .add_system_set(
+                SystemSet::on_exit(AppState::Results).with_system(despawn_screen::<OnResults>),
+            );
+        menu::add_menu_systems(app, AppState::Results);
+    }
+
+    fn name(&self) -> &str {
+        "results"
+    }
+}
@@ -0,0 +1,330 @@
+// copyright 2022 Remi Bernotavicius
+
+//! How a match is won. Every player starts with some lives and loses one each time they are stomped
+//! on, scoring a point for the player who stomped them. The match ends when the chosen
+//! `WinCondition` is met, or when fewer than two frogs are left in it.
+
+use super::level;
+use super::AppState;
+use bevy::prelude::*;
+use bevy::reflect::impl_reflect_value;
+use std::cmp::Reverse;
+
+/// How many frames make up a second of the match
+pub const FRAMES_PER_SECOND: u32 = 60;
+
+/// What ends a match, besides running out of frogs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WinCondition {
+    /// The first player to stomp this many times wins
+    Stomps(u32),
+    /// The match only ends when a single frog has lives left
+    LastFrogStanding,
+    /// The player with the most stomps when time runs out wins
+    Timed { seconds: u32 },
+}
+
+impl WinCondition {
+    pub const ALL: [Self; 3] = [
+        Self::Stomps(5),
+        Self::LastFrogStanding,
+        Self::Timed { seconds: 60 },
+    ];
+
+    pub fn name(self) -> String {
+        match self {
+            Self::Stomps(stomps) => format!("first to {stomps}"),
+            Self::LastFrogStanding => "last frog standing".into(),
+            Self::Timed { seconds } => format!("{seconds} seconds"),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|w| w.name() == name)
+    }
+
+    /// The condition after this one, going back to the first after the last
+    pub fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|&w| w == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+}
+
+/// The rules the next match is played with
+pub struct MatchRules {
+    win: WinCondition,
+    /// Which of the `level::BUILTIN` levels the match is played in
+    level: usize,
+    pub lives: u32,
+}
+
+/// The name the chosen `WinCondition` is saved under
+const WIN_CONDITION_SETTING: &str = "win_condition";
+
+/// The name the chosen level is saved under
+const LEVEL_SETTING: &str = "level";
+
+const STARTING_LIVES: u32 = 3;
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        let win = super::load_setting(WIN_CONDITION_SETTING)
+            .and_then(|name| WinCondition::from_name(&name))
+            .unwrap_or(WinCondition::ALL[0]);
+        let level = super::load_setting(LEVEL_SETTING)
+            .and_then(|name| level::BUILTIN.iter().position(|&(n, _)| n == name))
+            .unwrap_or(0);
+        Self {
+            win,
+            level,
+            lives: STARTING_LIVES,
+        }
+    }
+}
+
+impl MatchRules {
+    pub fn win(&self) -> WinCondition {
+        self.win
+    }
+
+    /// Changes how matches are won, and remembers the choice for next time
+    pub fn set_win(&mut self, win: WinCondition) {
+        self.win = win;
+        super::save_setting(WIN_CONDITION_SETTING, &win.name());
+    }
+
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    /// Moves on to the next level, going back to the first after the last, and remembers the
+    /// choice for next time
+    pub fn next_level(&mut self) {
+        self.level = (self.level + 1) % level::BUILTIN.len();
+        super::save_setting(LEVEL_SETTING, level::BUILTIN[self.level].0);
+    }
+
+    /// Identifies these rules, played on `level`, over the network. Peers only play each other when
+    /// their keys are the same, so every peer steps the match the same way on the same level.
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    pub fn session_key(&self, level: &level::Level) -> String {
+        let win = WinCondition::ALL.iter().position(|&w| w == self.win);
+        format!(
+            "win{}_lives{}_level{}_{:08x}",
+            win.unwrap_or(0),
+            self.lives,
+            self.level,
+            level.checksum()
+        )
+    }
+
+    /// How many frames are left before time runs out, if the match is timed
+    pub fn frames_left(&self, clock: &MatchClock) -> Option<u32> {
+        match self.win {
+            WinCondition::Timed { seconds } => {
+                Some((seconds * FRAMES_PER_SECOND).saturating_sub(clock.0))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether a match with these players has been won
+    pub fn is_over(&self, clock: &MatchClock, players: &[Standing]) -> bool {
+        let frogs_left = players.iter().filter(|p| p.lives > 0).count();
+        // A match with a single player only ends once they are out
+        if frogs_left < players.len().min(2) {
+            return true;
+        }
+        match self.win {
+            WinCondition::Stomps(stomps) => players.iter().any(|p| p.score >= stomps),
+            WinCondition::LastFrogStanding => false,
+            WinCondition::Timed { .. } => self.frames_left(clock) == Some(0),
+        }
+    }
+
+    /// Orders the players from first to last place. Only the last frog standing matters when that
+    /// is how the match is won, otherwise it is whoever stomped the most, with ties going to
+    /// whoever has the most lives left.
+    pub fn standings(&self, mut players: Vec<Standing>) -> Vec<Standing> {
+        match self.win {
+            WinCondition::LastFrogStanding => {
+                players.sort_by_key(|p| (Reverse(p.lives), Reverse(p.score), p.handle))
+            }
+            _ => players.sort_by_key(|p| (Reverse(p.score), Reverse(p.lives), p.handle)),
+        }
+        players
+    }
+}
+
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Lives(pub u32);
+
+impl_reflect_value!(Lives);
+
+/// How many times the player has stomped on another player
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Score(pub u32);
+
+impl_reflect_value!(Score);
+
+/// How many frames of the match have been played. This is rolled back along with the players, so
+/// timed matches end on the same frame for everyone.
+#[derive(Component, Clone, Default)]
+pub struct MatchClock(pub u32);
+
+impl_reflect_value!(MatchClock);
+
+/// The `MatchClock` reading the match was won at, if it has been. This is decided while stepping
+/// the match and rolled back with it, so a win predicted from inputs that turn out differently is
+/// taken back.
+#[derive(Component, Clone, Default)]
+pub struct MatchEnd(pub Option<u32>);
+
+impl_reflect_value!(MatchEnd);
+
+/// How a player did in a match
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Standing {
+    pub handle: u32,
+    pub lives: u32,
+    pub score: u32,
+}
+
+/// The outcome of the last match, shown on the results screen
+pub struct MatchResult {
+    /// From first to last place
+    pub standings: Vec<Standing>,
+    /// The game the match was played in, which a rematch goes back to
+    pub game: AppState,
+}
+
+impl MatchResult {
+    /// The player in first place, unless they are tied with the player in second place
+    pub fn winner(&self) -> Option<u32> {
+        match &self.standings[..] {
+            [first, second, ..] if (first.score, first.lives) == (second.score, second.lives) => {
+                None
+            }
+            [first, ..] => Some(first.handle),
+            [] => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures;
+    use super::*;
+    use euclid::Point2D;
+
+    fn standing(handle: u32, lives: u32, score: u32) -> Standing {
+        Standing {
+            handle,
+            lives,
+            score,
+        }
+    }
+
+    fn rules(win: WinCondition) -> MatchRules {
+        MatchRules {
+            win,
+            level: 0,
+            lives: STARTING_LIVES,
+        }
+    }
+
+    #[test]
+    fn win_conditions() {
+        let clock = MatchClock(0);
+        let playing = [standing(0, 3, 1), standing(1, 1, 2)];
+
+        let first_to_two = rules(WinCondition::Stomps(2));
+        assert!(first_to_two.is_over(&clock, &playing));
+        assert!(!rules(WinCondition::Stomps(3)).is_over(&clock, &playing));
+
+        let last_frog = rules(WinCondition::LastFrogStanding);
+        assert!(!last_frog.is_over(&clock, &playing));
+        assert!(last_frog.is_over(&clock, &[standing(0, 3, 0), standing(1, 0, 3)]));
+
+        let timed = rules(WinCondition::Timed { seconds: 2 });
+        assert!(!timed.is_over(&MatchClock(119), &playing));
+        assert_eq!(timed.frames_left(&MatchClock(119)), Some(1));
+        assert!(timed.is_over(&MatchClock(120), &playing));
+        assert_eq!(timed.frames_left(&MatchClock(500)), Some(0));
+
+        // A lone player keeps playing until they are out
+        assert!(!last_frog.is_over(&clock, &[standing(0, 1, 0)]));
+        assert!(last_frog.is_over(&clock, &[standing(0, 0, 0)]));
+    }
+
+    #[test]
+    fn standings_order() {
+        let players = vec![standing(0, 1, 3), standing(1, 3, 1), standing(2, 3, 3)];
+        let handles = |rules: MatchRules| -> Vec<u32> {
+            rules
+                .standings(players.clone())
+                .iter()
+                .map(|p| p.handle)
+                .collect()
+        };
+        assert_eq!(handles(rules(WinCondition::Stomps(5))), [2, 0, 1]);
+        assert_eq!(handles(rules(WinCondition::LastFrogStanding)), [2, 1, 0]);
+    }
+
+    #[test]
+    fn match_winner() {
+        let result = |standings| MatchResult {
+            standings,
+            game: AppState::SinglePlayerGame,
+        };
+        assert_eq!(
+            result(vec![standing(1, 2, 3), standing(0, 2, 1)]).winner(),
+            Some(1)
+        );
+        assert_eq!(
+            result(vec![standing(1, 2, 3), standing(0, 2, 3)]).winner(),
+            None
+        );
+        assert_eq!(result(vec![standing(0, 0, 0)]).winner(), Some(0));
+    }
+
+    #[test]
+    fn session_key_covers_the_rules() {
+        let level = fixtures::level();
+        let key = rules(WinCondition::Stomps(5)).session_key(&level);
+        assert_eq!(rules(WinCondition::Stomps(5)).session_key(&level), key);
+        assert_ne!(
+            rules(WinCondition::LastFrogStanding).session_key(&level),
+            key
+        );
+
+        let mut more_lives = rules(WinCondition::Stomps(5));
+        more_lives.lives += 1;
+        assert_ne!(more_lives.session_key(&level), key);
+
+        let mut next_level = rules(WinCondition::Stomps(5));
+        next_level.level += 1;
+        assert_ne!(next_level.session_key(&level), key);
+    }
+
+    #[test]
+    fn session_key_covers_the_level() {
+        let rules = rules(WinCondition::Stomps(5));
+        let level = fixtures::level();
+        let key = rules.session_key(&level);
+
+        // A level reloaded with different contents no longer matches
+        let mut reloaded = level.clone();
+        reloaded.spawns.push(Point2D::new(5, 6));
+        assert_ne!(rules.session_key(&reloaded), key);
+        assert_eq!(rules.session_key(&level.clone()), key);
+    }
+
+    #[test]
+    fn win_condition_names() {
+        for win in WinCondition::ALL {
+            assert_eq!(WinCondition::from_name(&win.name()), Some(win));
+        }
+        assert_eq!(WinCondition::ALL[2].next(), WinCondition::ALL[0]);
+    }
+}
@@ -0,0 +1,161 @@
+// copyright 2022 Remi Bernotavicius
+
+use super::capture::{RecordClip, Screenshot};
+use super::input::{Input, InputStream};
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use enumset::EnumSet;
+use std::io::{self, BufRead as _};
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread;
+
+impl FromStr for Input {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "up" => Ok(Self::Up),
+            "down" => Ok(Self::Down),
+            "left" => Ok(Self::Left),
+            "right" => Ok(Self::Right),
+            "primary" => Ok(Self::Primary),
+            "secondary" => Ok(Self::Secondary),
+            _ => Err(format!("unknown input {s:?}")),
+        }
+    }
+}
+
+/// Input for running without a browser. Each line of the script is the whitespace separated set
+/// of inputs pressed for one frame, e.g. "left primary". An empty line is a frame with no input.
+/// Including "screenshot" in a line saves an image of that frame. Including "clip" starts
+/// recording, and including it again saves the last few seconds recorded as an animated GIF.
+pub struct InputScript {
+    recv: Receiver<ScriptFrame>,
+}
+
+#[derive(Default)]
+struct ScriptFrame {
+    inputs: EnumSet<Input>,
+    screenshot: bool,
+    clip: bool,
+}
+
+fn parse_script_line(line: &str) -> Result<ScriptFrame, String> {
+    let mut frame = ScriptFrame::default();
+    for word in line.split_whitespace() {
+        if word == "screenshot" {
+            frame.screenshot = true;
+        } else if word == "clip" {
+            frame.clip = true;
+        } else {
+            frame.inputs.insert(Input::from_str(word)?);
+        }
+    }
+    Ok(frame)
+}
+
+impl InputScript {
+    pub fn from_lines(lines: impl IntoIterator<Item = impl AsRef<str>>) -> Result<Self, String> {
+        let (send, recv) = channel();
+        for line in lines {
+            send.send(parse_script_line(line.as_ref())?).unwrap();
+        }
+        Ok(Self { recv })
+    }
+
+    /// Reads the script from stdin as it arrives, one frame per line.
+    pub fn from_stdin() -> Self {
+        let (send, recv) = channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let line = line.expect("failed to read stdin");
+                match parse_script_line(&line) {
+                    Ok(frame) => {
+                        if send.send(frame).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => log::warn!("ignoring input line {line:?}: {e}"),
+                }
+            }
+        });
+        Self { recv }
+    }
+
+    /// Feeds the next frame of the script to the `InputStream`. Exits the app once the script is
+    /// finished.
+    pub fn drive(
+        script: NonSend<Self>,
+        mut input_stream: NonSendMut<InputStream>,
+        mut screenshots: EventWriter<Screenshot>,
+        mut clips: EventWriter<RecordClip>,
+        mut exit: EventWriter<AppExit>,
+    ) {
+        match script.recv.try_recv() {
+            Ok(frame) => {
+                for i in frame.inputs {
+                    input_stream.put(i);
+                }
+                if frame.screenshot {
+                    screenshots.send(Screenshot);
+                }
+                if frame.clip {
+                    clips.send(RecordClip);
+                }
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => exit.send(AppExit),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_parsed_in_to_frames() {
+        let frame = parse_script_line("left  primary\tup").unwrap();
+        assert_eq!(frame.inputs, Input::Left | Input::Primary | Input::Up);
+        assert!(!frame.screenshot);
+        assert!(!frame.clip);
+
+        let frame = parse_script_line("screenshot right clip").unwrap();
+        assert_eq!(frame.inputs, EnumSet::only(Input::Right));
+        assert!(frame.screenshot);
+        assert!(frame.clip);
+
+        let frame = parse_script_line("").unwrap();
+        assert!(frame.inputs.is_empty());
+        assert!(!frame.screenshot);
+        assert!(!frame.clip);
+    }
+
+    #[test]
+    fn unknown_words_are_rejected() {
+        let error = parse_script_line("left jump").err().unwrap();
+        assert_eq!(error, "unknown input \"jump\"");
+        let error = parse_script_line("Left").err().unwrap();
+        assert_eq!(error, "unknown input \"Left\"");
+
+        let error = InputScript::from_lines(["up", "", "down screenshots"])
+            .err()
+            .unwrap();
+        assert_eq!(error, "unknown input \"screenshots\"");
+    }
+
+    #[test]
+    fn script_frames_are_read_in_order() {
+        let script = InputScript::from_lines(["down", "", "secondary"]).unwrap();
+        let frames: Vec<_> = script.recv.try_iter().map(|f| f.inputs).collect();
+        assert_eq!(
+            frames,
+            [
+                EnumSet::only(Input::Down),
+                EnumSet::empty(),
+                EnumSet::only(Input::Secondary)
+            ]
+        );
+    }
+}
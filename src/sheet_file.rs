@@ -0,0 +1,134 @@
+// copyright 2022 Remi Bernotavicius
+
+//! The file format for `SpriteSheet`s, laid out as described in `file_format` with the magic bytes
+//! `FQBS`.
+//!
+//! Files from before there was a header are just the bincode encoded sheet. They are read as
+//! version 0 and written back out in the current version.
+
+use super::file_format::{FileError, FileErrorKind, FileFormat};
+use super::graphics::{SpriteData, SpriteSheet};
+
+/// `SpriteSheet::to_bytes` writes the `version` here. Bump it when changing `SpriteSheet` or
+/// `SpriteData`, and teach `SpriteSheet::from_bytes` to read the previous version.
+const FORMAT: FileFormat = FileFormat {
+    name: "sprite sheet",
+    magic: b"FQBS",
+    version: 1,
+};
+
+/// Checks the sprite's data has a pixel for every point in its size
+fn validate_sprite(name: &str, data: &SpriteData) -> Result<(), String> {
+    let expected = usize::try_from(data.size.width)
+        .ok()
+        .zip(usize::try_from(data.size.height).ok())
+        .map(|(w, h)| w * h);
+    if expected != Some(data.data.len()) {
+        return Err(format!(
+            "sprite {name:?} is {}x{} but has {} pixels",
+            data.size.width,
+            data.size.height,
+            data.data.len()
+        ));
+    }
+    Ok(())
+}
+
+impl SpriteSheet {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FileError> {
+        let sheet: Self = if FORMAT.has_header(bytes) {
+            // Version 1 is the only one with a header so far
+            let (_version, body) = FORMAT.read(bytes)?;
+            FORMAT.decode(body)?
+        } else {
+            // Version 0, which has the same encoding as version 1 but no header
+            FORMAT.decode(bytes)?
+        };
+        for (name, data) in sheet.sprites() {
+            validate_sprite(name, data).map_err(|e| FORMAT.error(FileErrorKind::Invalid(e)))?;
+        }
+        Ok(sheet)
+    }
+
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        FORMAT.write(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures::{sheet, sprites};
+    use super::*;
+    use euclid::Size2D;
+
+    #[test]
+    fn round_trip() {
+        let sheet = sheet();
+        let bytes = sheet.to_bytes();
+        assert!(bytes.starts_with(FORMAT.magic));
+        assert_eq!(
+            sprites(&SpriteSheet::from_bytes(&bytes).unwrap()),
+            sprites(&sheet)
+        );
+    }
+
+    #[test]
+    fn reads_unversioned_sheets() {
+        let sheet = sheet();
+        let legacy = bincode::serialize(&sheet).unwrap();
+        assert_eq!(
+            sprites(&SpriteSheet::from_bytes(&legacy).unwrap()),
+            sprites(&sheet)
+        );
+    }
+
+    #[test]
+    fn rejects_corrupt_sheets() {
+        let mut bytes = sheet().to_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(matches!(
+            SpriteSheet::from_bytes(&bytes),
+            Err(FileError {
+                kind: FileErrorKind::ChecksumMismatch { .. },
+                ..
+            })
+        ));
+
+        assert!(matches!(
+            SpriteSheet::from_bytes(&bytes[..6]),
+            Err(FileError {
+                kind: FileErrorKind::Truncated,
+                ..
+            })
+        ));
+
+        let mut bytes = sheet().to_bytes();
+        bytes[FORMAT.magic.len()] = 2;
+        assert!(matches!(
+            SpriteSheet::from_bytes(&bytes),
+            Err(FileError {
+                kind: FileErrorKind::UnsupportedVersion(2),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_sprites_with_wrong_pixel_count() {
+        let mut sheet = sheet();
+        sheet.insert_sprite(
+            "b",
+            SpriteData {
+                size: Size2D::new(2, 2),
+                data: vec![],
+            },
+        );
+        let error = SpriteSheet::from_bytes(&sheet.to_bytes()).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "invalid sprite sheet: sprite \"b\" is 2x2 but has 0 pixels"
+        );
+    }
+}
@@ -0,0 +1,171 @@
+// copyright 2022 Remi Bernotavicius
+
+//! Golden image tests. The game is run headlessly and the final frame is compared against a BMP
+//! checked in to `snapshots/`. Set `UPDATE_SNAPSHOTS=1` to write new golden images. When a frame
+//! doesn't match, the actual frame and an image highlighting the differences are written to
+//! `target/snapshots/`.
+
+use super::game::FpsCounterTextBox;
+use super::renderer::Framebuffer;
+use super::script::InputScript;
+use super::{headless_app, AppState};
+use bevy::prelude::*;
+use std::path::{Path, PathBuf};
+
+fn snapshot_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("snapshots")
+}
+
+fn output_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("target/snapshots")
+}
+
+/// Runs the game starting in `state` for one frame per line of `script` and returns the last frame
+/// drawn.
+fn render(state: AppState, script: &[&str]) -> bmp::Image {
+    render_app(
+        headless_app(state, InputScript::from_lines(script).unwrap()),
+        script,
+    )
+}
+
+/// Like `render`, for an app which has been set up beforehand
+fn render_app(mut app: App, script: &[&str]) -> bmp::Image {
+    assert!(!script.is_empty());
+
+    for _ in script {
+        app.update();
+
+        // The FPS counter depends on wall-clock time
+        let fps_counters: Vec<Entity> = app
+            .world
+            .query_filtered::<Entity, With<FpsCounterTextBox>>()
+            .iter(&app.world)
+            .collect();
+        for entity in fps_counters {
+            app.world.despawn(entity);
+        }
+    }
+
+    app.world
+        .get_non_send_resource::<Framebuffer>()
+        .unwrap()
+        .to_bmp()
+}
+
+/// Returns an image with the differing pixels in red, and the rest faded out, along with the count
+/// of differing pixels.
+fn diff(expected: &bmp::Image, actual: &bmp::Image) -> (bmp::Image, usize) {
+    let mut image = bmp::Image::new(expected.get_width(), expected.get_height());
+    let mut differences = 0;
+    for (x, y) in expected.coordinates() {
+        let e = expected.get_pixel(x, y);
+        let a = actual.get_pixel(x, y);
+        let p = if e == a {
+            bmp::Pixel::new(e.r / 4, e.g / 4, e.b / 4)
+        } else {
+            differences += 1;
+            bmp::Pixel::new(255, 0, 0)
+        };
+        image.set_pixel(x, y, p);
+    }
+    (image, differences)
+}
+
+fn assert_snapshot(name: &str, actual: bmp::Image) {
+    let golden_path = snapshot_dir().join(format!("{name}.bmp"));
+
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        std::fs::create_dir_all(snapshot_dir()).unwrap();
+        actual.save(&golden_path).unwrap();
+        return;
+    }
+
+    let expected =
+        bmp::open(&golden_path).unwrap_or_else(|e| panic!("failed to open {golden_path:?}: {e}"));
+    assert_eq!(
+        (expected.get_width(), expected.get_height()),
+        (actual.get_width(), actual.get_height()),
+        "snapshot {name} has the wrong size"
+    );
+
+    let (diff_image, differences) = diff(&expected, &actual);
+    if differences > 0 {
+        std::fs::create_dir_all(output_dir()).unwrap();
+        let actual_path = output_dir().join(format!("{name}.actual.bmp"));
+        let diff_path = output_dir().join(format!("{name}.diff.bmp"));
+        actual.save(&actual_path).unwrap();
+        diff_image.save(&diff_path).unwrap();
+        panic!(
+            "snapshot {name} differs by {differences} pixels, \
+            see {actual_path:?} and {diff_path:?}"
+        );
+    }
+}
+
+mod tests {
+    use super::super::rules::{MatchResult, Standing};
+    use super::*;
+
+    #[test]
+    fn diff_marks_changed_pixels() {
+        let a = bmp::Image::new(2, 2);
+        let mut b = bmp::Image::new(2, 2);
+        b.set_pixel(1, 0, bmp::Pixel::new(1, 2, 3));
+
+        let (image, differences) = diff(&a, &b);
+        assert_eq!(differences, 1);
+        assert_eq!(image.get_pixel(1, 0), bmp::Pixel::new(255, 0, 0));
+        assert_eq!(image.get_pixel(0, 0), bmp::Pixel::new(0, 0, 0));
+    }
+
+    #[test]
+    fn menu() {
+        assert_snapshot("menu", render(AppState::Menu, &["", ""]));
+    }
+
+    #[test]
+    fn sprite_editor() {
+        assert_snapshot(
+            "sprite_editor",
+            render(
+                AppState::SpriteEditor,
+                &["", "right", "down", "primary", "secondary", "down"],
+            ),
+        );
+    }
+
+    #[test]
+    fn single_player_start() {
+        assert_snapshot(
+            "single_player_start",
+            render(AppState::SinglePlayerGame, &[""]),
+        );
+    }
+
+    #[test]
+    fn single_player_frame_60() {
+        let mut script = vec!["right"; 10];
+        script.resize(60, "");
+        assert_snapshot(
+            "single_player_frame_60",
+            render(AppState::SinglePlayerGame, &script),
+        );
+    }
+
+    #[test]
+    fn results() {
+        let script = ["", "down", ""];
+        let mut app = headless_app(AppState::Results, InputScript::from_lines(script).unwrap());
+        let standing = |handle, lives, score| Standing {
+            handle,
+            lives,
+            score,
+        };
+        app.insert_resource(MatchResult {
+            standings: vec![standing(1, 2, 5), standing(0, 0, 3)],
+            game: AppState::SinglePlayerGame,
+        });
+        assert_snapshot("results", render_app(app, &script));
+    }
+}
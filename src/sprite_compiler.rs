@@ -0,0 +1,366 @@
+// copyright 2022 Remi Bernotavicius
+
+//! Converts between `SpriteSheet` files and BMP images, so sprites can be drawn in an image editor.
+//!
+//! A sheet is described by a manifest, a text file like:
+//!
+//! ```text
+//! # comments start with '#'
+//! image font.bmp
+//! cell 8 16
+//! a 0 0
+//! b 1 0
+//! frog_sit 2 0 10 10
+//! ```
+//!
+//! `image` is the BMP to read, relative to the manifest. `cell` is the size of the grid the image
+//! is divided in to. Every other line names a sprite and the column and row of the cell it is in,
+//! optionally followed by a size when the sprite is smaller than a cell. In sprite names `\s` is a
+//! space, `\#` is a '#' and `\\` is a backslash.
+//!
+//! Each pixel becomes the `PalletColor` whose color in the classic `PALLET` is closest to it.
+
+use super::graphics::{PointIterExt as _, SpriteData, SpriteSheet, PALLET};
+use super::renderer::{Color, PalletColor, Pixels};
+use euclid::{Point2D, Rect, Size2D};
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How many sprites are in each row of an image written by `dump`
+const DUMP_COLUMNS: i32 = 16;
+
+struct ManifestEntry {
+    name: String,
+    rect: Rect<i32, Pixels>,
+}
+
+struct Manifest {
+    image: PathBuf,
+    entries: Vec<ManifestEntry>,
+}
+
+fn parse_int(word: Option<&str>, what: &str) -> Result<i32, String> {
+    let word = word.ok_or_else(|| format!("missing {what}"))?;
+    word.parse().map_err(|_| format!("invalid {what} {word:?}"))
+}
+
+fn unescape_name(name: &str) -> Result<String, String> {
+    let mut unescaped = String::new();
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => unescaped.push(' '),
+            Some('#') => unescaped.push('#'),
+            Some('\\') => unescaped.push('\\'),
+            _ => return Err(format!("invalid escape in sprite name {name:?}")),
+        }
+    }
+    Ok(unescaped)
+}
+
+fn escape_name(name: &str) -> String {
+    let mut escaped = String::new();
+    for c in name.chars() {
+        match c {
+            ' ' => escaped.push_str("\\s"),
+            '#' => escaped.push_str("\\#"),
+            '\\' => escaped.push_str("\\\\"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl Manifest {
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut image = None;
+        let mut cell = None;
+        let mut entries = vec![];
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            Self::parse_line(line, &mut image, &mut cell, &mut entries)
+                .map_err(|e| format!("line {}: {e}", n + 1))?;
+        }
+        Ok(Self {
+            image: image.ok_or("manifest has no image")?,
+            entries,
+        })
+    }
+
+    fn parse_line(
+        line: &str,
+        image: &mut Option<PathBuf>,
+        cell: &mut Option<Size2D<i32, Pixels>>,
+        entries: &mut Vec<ManifestEntry>,
+    ) -> Result<(), String> {
+        let mut words = line.split_whitespace();
+        match words.next().unwrap() {
+            "image" => {
+                *image = Some(words.next().ok_or("missing image path")?.into());
+            }
+            "cell" => {
+                let size = Size2D::new(
+                    parse_int(words.next(), "cell width")?,
+                    parse_int(words.next(), "cell height")?,
+                );
+                if size.is_empty() {
+                    return Err(format!("invalid cell size {size:?}"));
+                }
+                *cell = Some(size);
+            }
+            name => {
+                let cell = cell.ok_or("sprite before cell size")?;
+                let name = unescape_name(name)?;
+                let column = parse_int(words.next(), "column")?;
+                let row = parse_int(words.next(), "row")?;
+                let size = match words.next() {
+                    Some(width) => Size2D::new(
+                        parse_int(Some(width), "width")?,
+                        parse_int(words.next(), "height")?,
+                    ),
+                    None => cell,
+                };
+                if size.is_empty() {
+                    return Err(format!("invalid size {size:?} for {name:?}"));
+                }
+                let origin = Point2D::new(column * cell.width, row * cell.height);
+                entries.push(ManifestEntry {
+                    name,
+                    rect: Rect::new(origin, size),
+                });
+            }
+        }
+        if let Some(extra) = words.next() {
+            return Err(format!("unexpected {extra:?}"));
+        }
+        Ok(())
+    }
+}
+
+fn color_distance(a: Color, b: bmp::Pixel) -> u32 {
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    d(a.r, b.r) + d(a.g, b.g) + d(a.b, b.b)
+}
+
+fn quantise(pixel: bmp::Pixel) -> PalletColor {
+    PalletColor::ALL
+        .into_iter()
+        .min_by_key(|&c| color_distance(PALLET.color(c), pixel))
+        .unwrap()
+}
+
+fn sheet_from_image(manifest: &Manifest, image: &bmp::Image) -> Result<SpriteSheet, String> {
+    let image_rect = Rect::<i32, Pixels>::from_size(Size2D::new(
+        image.get_width() as i32,
+        image.get_height() as i32,
+    ));
+    let mut sheet = SpriteSheet::default();
+    for entry in &manifest.entries {
+        if !image_rect.contains_rect(&entry.rect) {
+            return Err(format!(
+                "{:?} at {:?} is outside of the image",
+                entry.name, entry.rect
+            ));
+        }
+        let data = entry
+            .rect
+            .point_iter()
+            .map(|p| quantise(image.get_pixel(p.x as u32, p.y as u32)))
+            .collect();
+        sheet.insert_sprite(
+            entry.name.clone(),
+            SpriteData {
+                size: entry.rect.size,
+                data,
+            },
+        );
+    }
+    Ok(sheet)
+}
+
+fn invalid_data(error: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Reads the manifest and the image it refers to and builds a `SpriteSheet` from them
+fn load_manifest(manifest_path: &Path) -> io::Result<SpriteSheet> {
+    let manifest = Manifest::parse(&std::fs::read_to_string(manifest_path)?)
+        .map_err(|e| invalid_data(format!("{}: {e}", manifest_path.display())))?;
+    let image_path = manifest_path
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(&manifest.image);
+    let image = bmp::open(&image_path)
+        .map_err(|e| invalid_data(format!("{}: {e}", image_path.display())))?;
+    sheet_from_image(&manifest, &image)
+        .map_err(|e| invalid_data(format!("{}: {e}", manifest_path.display())))
+}
+
+/// Builds a `SpriteSheet` file from the manifest and image described in the module documentation
+pub fn compile_sprite_sheet(manifest_path: &Path, output_path: &Path) -> io::Result<()> {
+    let sheet = load_manifest(manifest_path)?;
+    std::fs::write(output_path, sheet.to_bytes())
+}
+
+/// Lays the sprites out in a grid, sorted by name, returning the image and a manifest for it
+fn dump_to_image(sheet: &SpriteSheet, image_name: &str) -> (bmp::Image, String) {
+    let mut sprites: Vec<_> = sheet.sprites().collect();
+    sprites.sort_by_key(|&(name, _)| name);
+
+    let cell = sprites
+        .iter()
+        .fold(Size2D::<i32, Pixels>::new(1, 1), |cell, (_, data)| {
+            cell.max(data.size)
+        });
+    let columns = DUMP_COLUMNS.min(sprites.len().max(1) as i32);
+    let rows = (sprites.len() as i32 + columns - 1) / columns;
+    let mut image = bmp::Image::new(
+        (columns * cell.width) as u32,
+        (rows.max(1) * cell.height) as u32,
+    );
+    let background = PALLET.color(PalletColor::Color1);
+    for (x, y) in image.coordinates() {
+        image.set_pixel(
+            x,
+            y,
+            bmp::Pixel::new(background.r, background.g, background.b),
+        );
+    }
+
+    let mut manifest = String::new();
+    writeln!(manifest, "image {image_name}").unwrap();
+    writeln!(manifest, "cell {} {}", cell.width, cell.height).unwrap();
+    for (i, (name, data)) in sprites.into_iter().enumerate() {
+        let (column, row) = (i as i32 % columns, i as i32 / columns);
+        write!(manifest, "{} {column} {row}", escape_name(name)).unwrap();
+        if data.size != cell {
+            write!(manifest, " {} {}", data.size.width, data.size.height).unwrap();
+        }
+        writeln!(manifest).unwrap();
+
+        let origin = Point2D::new(column * cell.width, row * cell.height);
+        for p in Rect::from_size(data.size).point_iter() {
+            let color = PALLET.color(data.get_pixel(p));
+            let p = origin + p.to_vector();
+            image.set_pixel(
+                p.x as u32,
+                p.y as u32,
+                bmp::Pixel::new(color.r, color.g, color.b),
+            );
+        }
+    }
+    (image, manifest)
+}
+
+/// Writes the sprites from the `SpriteSheet` file to `<prefix>.bmp`, along with a manifest
+/// `<prefix>.txt` which `compile_sprite_sheet` can turn back in to the same sheet.
+pub fn dump_sprite_sheet(sheet_path: &Path, prefix: &Path) -> io::Result<()> {
+    let sheet = SpriteSheet::from_bytes(&std::fs::read(sheet_path)?)
+        .map_err(|e| invalid_data(format!("{}: {e}", sheet_path.display())))?;
+    let image_path = prefix.with_extension("bmp");
+    let image_name = image_path.file_name().unwrap().to_string_lossy();
+    let (image, manifest) = dump_to_image(&sheet, &image_name);
+    image.save(&image_path)?;
+    std::fs::write(prefix.with_extension("txt"), manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures::sprites;
+    use super::*;
+
+    #[test]
+    fn parse_manifest() {
+        let manifest = Manifest::parse(
+            "# a comment\n\
+             image sheet.bmp\n\
+             cell 8 16\n\
+             a 0 0\n\
+             \\s 1 2\n\
+             \\#\\\\ 3 0 4 5\n",
+        )
+        .unwrap();
+        assert_eq!(manifest.image, Path::new("sheet.bmp"));
+        let entries: Vec<_> = manifest
+            .entries
+            .iter()
+            .map(|e| (e.name.as_str(), e.rect))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("a", Rect::new(Point2D::new(0, 0), Size2D::new(8, 16))),
+                (" ", Rect::new(Point2D::new(8, 32), Size2D::new(8, 16))),
+                ("#\\", Rect::new(Point2D::new(24, 0), Size2D::new(4, 5))),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_manifest_errors() {
+        let error = |text| Manifest::parse(text).err().unwrap();
+        assert_eq!(error("cell 8 8\n"), "manifest has no image");
+        assert_eq!(
+            error("image a.bmp\na 0 0\n"),
+            "line 2: sprite before cell size"
+        );
+        assert_eq!(
+            error("image a.bmp\ncell 8 8\na 0 x\n"),
+            "line 3: invalid row \"x\""
+        );
+        assert_eq!(
+            error("image a.bmp\ncell 8 8\na 0 0 1\n"),
+            "line 3: missing height"
+        );
+    }
+
+    #[test]
+    fn quantise_to_nearest_pallet_color() {
+        for color in PalletColor::ALL {
+            let c = PALLET.color(color);
+            assert_eq!(quantise(bmp::Pixel::new(c.r, c.g, c.b)), color);
+        }
+        assert_eq!(quantise(bmp::Pixel::new(0, 0, 0)), PalletColor::Color1);
+        assert_eq!(
+            quantise(bmp::Pixel::new(255, 255, 255)),
+            PalletColor::Color4
+        );
+    }
+
+    #[test]
+    fn dump_and_compile_round_trip() {
+        for bytes in [
+            &include_bytes!("../assets/font.bin")[..],
+            &include_bytes!("../assets/frog.bin")[..],
+            &include_bytes!("../assets/tiles.bin")[..],
+        ] {
+            let sheet = SpriteSheet::from_bytes(bytes).unwrap();
+            let (image, manifest) = dump_to_image(&sheet, "sheet.bmp");
+            let manifest = Manifest::parse(&manifest).unwrap();
+            assert_eq!(
+                sprites(&sheet_from_image(&manifest, &image).unwrap()),
+                sprites(&sheet)
+            );
+        }
+    }
+
+    #[test]
+    fn checked_in_sources_match_assets() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+        for name in ["font", "frog", "tiles"] {
+            let compiled = load_manifest(&root.join(format!("{name}.txt"))).unwrap();
+            let sheet =
+                SpriteSheet::from_bytes(&std::fs::read(root.join(format!("{name}.bin"))).unwrap())
+                    .unwrap();
+            assert_eq!(sprites(&compiled), sprites(&sheet));
+        }
+    }
+}
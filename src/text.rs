@@ -0,0 +1,166 @@
+// copyright 2022 Remi Bernotavicius
+
+//! Lays out the text of a `TextBox`. Each character is as wide as its sprite in the font, and lines
+//! are `LINE_HEIGHT` apart.
+
+use super::graphics::SpriteSheet;
+use super::renderer::Pixels;
+use euclid::Size2D;
+
+/// The distance between the tops of two lines of text. The font's glyphs are taller than this, but
+/// the bottom of them is empty.
+pub const LINE_HEIGHT: i32 = 10;
+
+/// Where each line of text goes between the left and right sides of its box
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+impl Align {
+    /// How far from the left side of a box `width` wide a line `line_width` wide starts
+    pub fn offset(self, line_width: i32, width: i32) -> i32 {
+        match self {
+            Self::Left => 0,
+            Self::Center => (width - line_width) / 2,
+            Self::Right => width - line_width,
+        }
+    }
+}
+
+pub fn text_width(text: &str, font: &SpriteSheet) -> i32 {
+    text.chars().map(|c| font.tile_size(c.into()).width).sum()
+}
+
+/// Splits the word in to pieces which each fit in `width`, with at least one character in each
+fn break_word<'a>(word: &'a str, font: &SpriteSheet, width: i32) -> Vec<&'a str> {
+    let mut pieces = vec![];
+    let mut start = 0;
+    let mut piece_width = 0;
+    for (i, c) in word.char_indices() {
+        let w = font.tile_size(c.into()).width;
+        if i > start && piece_width + w > width {
+            pieces.push(&word[start..i]);
+            start = i;
+            piece_width = 0;
+        }
+        piece_width += w;
+    }
+    pieces.push(&word[start..]);
+    pieces
+}
+
+/// Splits the text in to lines at each '\n'. When given a width, lines are also split between
+/// words so they fit in it, and words too long for a line of their own are split too.
+pub fn lines(text: &str, font: &SpriteSheet, width: Option<i32>) -> Vec<String> {
+    let width = match width {
+        Some(width) => width,
+        None => return text.split('\n').map(String::from).collect(),
+    };
+    let space_width = text_width(" ", font);
+
+    let mut lines = vec![];
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        let mut line_width = 0;
+        for word in paragraph.split(' ') {
+            let word_width = text_width(word, font);
+            if !line.is_empty() {
+                if line_width + space_width + word_width <= width {
+                    line.push(' ');
+                    line.push_str(word);
+                    line_width += space_width + word_width;
+                    continue;
+                }
+                lines.push(std::mem::take(&mut line));
+            }
+
+            let mut pieces = break_word(word, font, width);
+            line = pieces.pop().unwrap().into();
+            line_width = text_width(&line, font);
+            lines.extend(pieces.into_iter().map(String::from));
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// The size of the area the text is drawn in, from the top of the first line to the bottom of the
+/// tallest glyph on the last.
+pub fn measure(text: &str, font: &SpriteSheet, width: Option<i32>) -> Size2D<i32, Pixels> {
+    let lines = lines(text, font, width);
+    let widest = lines.iter().map(|l| text_width(l, font)).max().unwrap_or(0);
+    let last_height = lines
+        .last()
+        .into_iter()
+        .flat_map(|l| l.chars())
+        .map(|c| font.tile_size(c.into()).height)
+        .max();
+    match last_height {
+        Some(h) => Size2D::new(widest, (lines.len() as i32 - 1) * LINE_HEIGHT + h),
+        None => Size2D::new(widest, (lines.len() as i32 - 1) * LINE_HEIGHT),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures;
+    use super::*;
+
+    #[test]
+    fn lines_split_at_newlines() {
+        let font = fixtures::sheet();
+        assert_eq!(lines("ab\n\ncd", &font, None), ["ab", "", "cd"]);
+        assert_eq!(lines("ab\n\ncd", &font, Some(100)), ["ab", "", "cd"]);
+    }
+
+    #[test]
+    fn lines_wrap_between_words() {
+        let font = fixtures::sheet();
+        // Each character is 2 pixels wide, so 5 characters fit in 10 pixels
+        assert_eq!(
+            lines("the quick brown fox", &font, Some(10)),
+            ["the", "quick", "brown", "fox"]
+        );
+        assert_eq!(
+            lines("a bc de fgh i", &font, Some(10)),
+            ["a bc", "de", "fgh i"]
+        );
+    }
+
+    #[test]
+    fn long_words_are_broken() {
+        let font = fixtures::sheet();
+        assert_eq!(
+            lines("ab abcdefghijkl c", &font, Some(10)),
+            ["ab", "abcde", "fghij", "kl c"]
+        );
+        // Even if not a single character fits
+        assert_eq!(lines("ab", &font, Some(1)), ["a", "b"]);
+    }
+
+    #[test]
+    fn measure_lines() {
+        let font = fixtures::sheet();
+        assert_eq!(measure("abc", &font, None), Size2D::new(6, 3));
+        assert_eq!(
+            measure("abc\nde", &font, None),
+            Size2D::new(6, LINE_HEIGHT + 3)
+        );
+        assert_eq!(measure("", &font, None), Size2D::new(0, 0));
+        assert_eq!(
+            measure("abc abc", &font, Some(10)),
+            Size2D::new(6, LINE_HEIGHT + 3)
+        );
+    }
+
+    #[test]
+    fn align_offsets() {
+        assert_eq!(Align::Left.offset(4, 10), 0);
+        assert_eq!(Align::Center.offset(4, 10), 3);
+        assert_eq!(Align::Right.offset(4, 10), 6);
+    }
+}
@@ -0,0 +1,177 @@
+// copyright 2022 Remi Bernotavicius
+
+//! The background of a level, a grid of tiles from the `Sheet::Tiles` sprite sheet drawn behind
+//! everything else.
+//!
+//! A tile is animated when the sheet has more frames of it, named after the tile with `_2`, `_3`
+//! and so on, e.g. `water`, `water_2` and `water_3`. Most tiles don't change, so they are drawn
+//! once in to a layer the size of the screen which is then copied on to the screen every frame.
+//! Only animated tiles are drawn a pixel at a time on top of it.
+
+use super::graphics::{Assets, ColorMap, Orientation, Sheet, SpriteSheet};
+use super::level::{Background, TILE_SIZE};
+use super::renderer::{Framebuffer, PalletColor, Pixels, Renderer, RENDER_RECT};
+use bevy::prelude::*;
+use euclid::Point2D;
+
+/// How many game frames each frame of an animated tile is shown for
+const TILE_FRAME_DURATION: u64 = 15;
+
+/// The number of cells across and down the screen
+const COLUMNS: i32 = RENDER_RECT.size.width / TILE_SIZE;
+const ROWS: i32 = RENDER_RECT.size.height / TILE_SIZE;
+
+#[derive(Component)]
+pub struct Tilemap {
+    /// The name of each different tile in the map
+    tiles: Vec<String>,
+    /// The index in `tiles` of each cell, row by row, or `None` for an empty cell
+    cells: Vec<Option<usize>>,
+    /// The names of the frames of each of the `tiles`, found in the sheet along with `layer`
+    frames: Vec<Vec<String>>,
+    /// Every tile with a single frame drawn over the background color, or `None` until it is next
+    /// drawn
+    layer: Option<Vec<PalletColor>>,
+}
+
+impl Tilemap {
+    pub fn new(background: &Background) -> Self {
+        let tiles: Vec<String> = background.tiles.iter().map(|(_, n)| n.clone()).collect();
+        let mut cells = vec![None; (COLUMNS * ROWS) as usize];
+        for (row, line) in background.rows.iter().take(ROWS as usize).enumerate() {
+            for (column, c) in line.chars().take(COLUMNS as usize).enumerate() {
+                cells[row * COLUMNS as usize + column] =
+                    background.tiles.iter().position(|&(t, _)| t == c);
+            }
+        }
+        Self {
+            tiles,
+            cells,
+            frames: vec![],
+            layer: None,
+        }
+    }
+
+    /// The frames of the named tile. Tiles the sheet doesn't have are a single frame, drawn as the
+    /// missing sprite.
+    fn find_frames(name: &str, sheet: &SpriteSheet) -> Vec<String> {
+        let more = (2..)
+            .map(|n| format!("{name}_{n}"))
+            .take_while(|frame| sheet.has_sprite(frame));
+        std::iter::once(name.to_owned()).chain(more).collect()
+    }
+
+    /// Where on screen each cell with a tile is, along with the index of the tile
+    fn placed_tiles(&self) -> impl Iterator<Item = (Point2D<i32, Pixels>, usize)> + '_ {
+        self.cells.iter().enumerate().filter_map(|(i, tile)| {
+            let (row, column) = (i as i32 / COLUMNS, i as i32 % COLUMNS);
+            Some((Point2D::new(column, row) * TILE_SIZE, (*tile)?))
+        })
+    }
+
+    fn draw_frame(
+        &self,
+        frame: &str,
+        p: Point2D<i32, Pixels>,
+        assets: &Assets,
+        renderer: &mut impl Renderer,
+    ) {
+        assets.sheet(Sheet::Tiles).draw_named(
+            frame,
+            p,
+            ColorMap::IDENTITY,
+            Orientation::default(),
+            RENDER_RECT,
+            renderer,
+        );
+    }
+
+    /// Draws the tiles which don't change in to a new `layer`
+    fn cache_layer(&mut self, assets: &Assets) {
+        let sheet = assets.sheet(Sheet::Tiles);
+        self.frames = self
+            .tiles
+            .iter()
+            .map(|name| Self::find_frames(name, sheet))
+            .collect();
+
+        // A new framebuffer starts out all `PalletColor::Color1`, the background color
+        let mut layer = Framebuffer::new();
+        for (p, tile) in self.placed_tiles() {
+            if let [frame] = &self.frames[tile][..] {
+                self.draw_frame(frame, p, assets, &mut layer);
+            }
+        }
+        self.layer = Some(layer.pallet_indices().to_vec());
+    }
+
+    /// Draws the layer again whenever the tiles in `Assets` change
+    pub fn update(assets: Res<Assets>, mut query: Query<&mut Self>) {
+        for mut tilemap in query.iter_mut() {
+            if tilemap.layer.is_none() || assets.is_changed() {
+                tilemap.cache_layer(&assets);
+            }
+        }
+    }
+
+    /// Covers the whole screen with the tilemap, showing animated tiles as they are at `frame`
+    pub fn draw(&self, frame: u64, assets: &Assets, renderer: &mut impl Renderer) {
+        let layer = match &self.layer {
+            Some(layer) => layer,
+            None => return,
+        };
+        renderer.color_screen(layer);
+
+        for (p, tile) in self.placed_tiles() {
+            let frames = &self.frames[tile];
+            if frames.len() > 1 {
+                let current = (frame / TILE_FRAME_DURATION) as usize % frames.len();
+                self.draw_frame(&frames[current], p, assets, renderer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures::{self, pixel};
+    use super::*;
+
+    /// The shared level's background of mud, with animated water below it
+    fn tilemap() -> Tilemap {
+        Tilemap::new(&fixtures::level().background)
+    }
+
+    #[test]
+    fn static_tiles_are_cached() {
+        let assets = fixtures::assets();
+        let mut tilemap = tilemap();
+        tilemap.cache_layer(&assets);
+
+        assert_eq!(tilemap.frames, [vec!["mud"], vec!["water", "water_2"]]);
+        let layer = tilemap.layer.as_ref().unwrap();
+        let width = RENDER_RECT.size.width as usize;
+        assert_eq!(layer[TILE_SIZE as usize], PalletColor::Color3);
+        assert_eq!(layer[0], PalletColor::Color1);
+        // Animated tiles are left out of the layer
+        assert_eq!(layer[TILE_SIZE as usize * width], PalletColor::Color1);
+    }
+
+    #[test]
+    fn animated_tiles_change_over_time() {
+        let assets = fixtures::assets();
+        let mut tilemap = tilemap();
+        tilemap.cache_layer(&assets);
+
+        let mut renderer = Framebuffer::new();
+        tilemap.draw(0, &assets, &mut renderer);
+        assert_eq!(pixel(&renderer, 0, TILE_SIZE), PalletColor::Color2);
+        assert_eq!(pixel(&renderer, TILE_SIZE, 0), PalletColor::Color3);
+
+        tilemap.draw(TILE_FRAME_DURATION, &assets, &mut renderer);
+        assert_eq!(pixel(&renderer, 0, TILE_SIZE), PalletColor::Color4);
+
+        tilemap.draw(TILE_FRAME_DURATION * 2, &assets, &mut renderer);
+        assert_eq!(pixel(&renderer, 0, TILE_SIZE), PalletColor::Color2);
+    }
+}